@@ -10,14 +10,608 @@ use js::jsapi::{JSTracer};
 use util::task_state;
 use util::task_state::{SCRIPT, IN_GC};
 
-use std::cell::{RefCell, Ref, RefMut};
+use time;
+
+#[cfg(feature = "serialize")]
+use serialize::{Encodable, Encoder, Decodable, Decoder};
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::default::Default;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+#[cfg(not(ndebug))]
+use std::intrinsics::TypeId;
+use std::marker;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::rc::Rc;
+use std::thread::Thread;
+use std::time::duration::Duration;
+#[cfg(feature = "rwcell")]
+use std::cell::UnsafeCell;
+#[cfg(feature = "rwcell")]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// The guards returned by `DOMRefCell::borrow` and `DOMRefCell::borrow_mut`.
+///
+/// Import these from here rather than from `std::cell`, so that call sites
+/// don't depend on which guard types `DOMRefCell` uses.
+pub use std::cell::{Ref, RefMut};
 
 /// A mutable field in the DOM.
 ///
 /// This extends the API of `core::cell::RefCell` to allow unsafe access in
 /// certain situations, with dynamic checking in debug builds.
+///
+/// Its soundness relies on all access happening on the script thread, so it
+/// is neither `Send` nor `Sync`, whatever `T` is.
 pub struct DOMRefCell<T> {
     value: RefCell<T>,
+    diagnostics: CellDiagnostics,
+    _nosend: marker::NoSend,
+}
+
+/// Per-cell diagnostic state.
+///
+/// This is empty in release builds, so that it doesn't make every
+/// `DOMRefCell` bigger. Any debug-only state a cell needs belongs here,
+/// under `#[cfg(not(ndebug))]`, rather than in `DOMRefCell` itself; the
+/// `release_size` test checks that a release `DOMRefCell` is no bigger than
+/// a `RefCell`, and the `debug_size` test pins how much bigger a debug one
+/// is, so that growing this is a deliberate decision.
+#[cfg(not(ndebug))]
+struct CellDiagnostics {
+    /// The number of blocked `try_borrow_mut_attempts` calls since the last
+    /// `reset_blocked_attempts`.
+    blocked_attempts: Cell<u32>,
+    /// Whether `freeze` has been called. Freezing is only enforced in debug
+    /// builds, through this flag.
+    frozen: Cell<bool>,
+    /// When the last mutable borrow was taken, in nanoseconds, if the borrow
+    /// watchdog was enabled at the time.
+    mutably_borrowed_at: Cell<Option<u64>>,
+}
+
+#[cfg(not(ndebug))]
+impl CellDiagnostics {
+    fn new() -> CellDiagnostics {
+        CellDiagnostics {
+            blocked_attempts: Cell::new(0),
+            frozen: Cell::new(false),
+            mutably_borrowed_at: Cell::new(None),
+        }
+    }
+
+    fn blocked_attempts(&self) -> u32 {
+        self.blocked_attempts.get()
+    }
+
+    fn set_blocked_attempts(&self, attempts: u32) {
+        self.blocked_attempts.set(attempts)
+    }
+
+    fn frozen(&self) -> bool {
+        self.frozen.get()
+    }
+
+    fn set_frozen(&self, frozen: bool) {
+        self.frozen.set(frozen)
+    }
+
+    fn mutably_borrowed_at(&self) -> Option<u64> {
+        self.mutably_borrowed_at.get()
+    }
+
+    fn set_mutably_borrowed_at(&self, time: Option<u64>) {
+        self.mutably_borrowed_at.set(time)
+    }
+}
+
+#[cfg(ndebug)]
+struct CellDiagnostics;
+
+#[cfg(ndebug)]
+impl CellDiagnostics {
+    #[inline(always)] fn new() -> CellDiagnostics { CellDiagnostics }
+    #[inline(always)] fn blocked_attempts(&self) -> u32 { 0 }
+    #[inline(always)] fn set_blocked_attempts(&self, _: u32) { }
+    #[inline(always)] fn frozen(&self) -> bool { false }
+    #[inline(always)] fn set_frozen(&self, _: bool) { }
+    #[inline(always)] fn mutably_borrowed_at(&self) -> Option<u64> { None }
+    #[inline(always)] fn set_mutably_borrowed_at(&self, _: Option<u64>) { }
+}
+
+/// The reason a borrow of a `DOMRefCell` failed.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum BorrowFailure {
+    /// The value is currently mutably borrowed.
+    AlreadyMutablyBorrowed,
+    /// The value is currently borrowed, either mutably or immutably.
+    AlreadyBorrowed,
+    /// The borrow was attempted off the script thread.
+    WrongTaskState,
+    /// A mutable borrow was attempted after `DOMRefCell::freeze`. Only
+    /// reported in debug builds.
+    Frozen,
+    /// A mutable borrow of the `Poisonable` cell was released by a panic.
+    Poisoned,
+}
+
+/// An error returned by `DOMRefCell::try_borrow_checked`.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub struct BorrowError {
+    /// Why the borrow failed.
+    pub reason: BorrowFailure,
+}
+
+/// An error returned by `DOMRefCell::try_borrow_mut_checked`.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub struct BorrowMutError {
+    /// Why the borrow failed.
+    pub reason: BorrowFailure,
+}
+
+/// The borrow state of a `DOMRefCell`, as returned by
+/// `DOMRefCell::borrow_state`.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum BorrowState {
+    /// The value is not borrowed.
+    Unborrowed,
+    /// The value is immutably borrowed.
+    Shared,
+    /// The value is mutably borrowed.
+    Mutable,
+}
+
+/// The kind of borrow reported in a `BorrowEvent`.
+#[derive(Copy, Clone, PartialEq, Show)]
+pub enum BorrowEventKind {
+    /// An immutable borrow was taken.
+    Shared,
+    /// A mutable borrow was taken.
+    Mutable,
+}
+
+/// A successful borrow of a `DOMRefCell`, as reported to the hook
+/// registered with `set_borrow_hook`.
+#[derive(Copy, Clone, Show)]
+pub struct BorrowEvent {
+    /// The address of the borrowed cell.
+    pub cell: uint,
+    /// The kind of borrow that was taken.
+    pub kind: BorrowEventKind,
+}
+
+#[cfg(not(ndebug))]
+thread_local!(static BORROW_HOOK: Cell<Option<fn(BorrowEvent)>> = Cell::new(None));
+
+/// Registers `hook` to be called on every successful `DOMRefCell` borrow on
+/// the current thread, replacing any previously registered hook.
+///
+/// Only borrows are reported: the guards are plain `Ref`/`RefMut`, so their
+/// release cannot be observed. In release builds, this does nothing.
+#[cfg(not(ndebug))]
+pub fn set_borrow_hook(hook: fn(BorrowEvent)) {
+    BORROW_HOOK.with(|h| h.set(Some(hook)));
+}
+
+#[cfg(ndebug)]
+#[inline(always)]
+pub fn set_borrow_hook(_: fn(BorrowEvent)) {
+}
+
+/// Unregisters the hook registered with `set_borrow_hook`, if any.
+#[cfg(not(ndebug))]
+pub fn clear_borrow_hook() {
+    BORROW_HOOK.with(|h| h.set(None));
+}
+
+#[cfg(ndebug)]
+#[inline(always)]
+pub fn clear_borrow_hook() {
+}
+
+#[cfg(not(ndebug))]
+fn report_borrow(cell: uint, kind: BorrowEventKind) {
+    BORROW_HOOK.with(|h| {
+        if let Some(hook) = h.get() {
+            hook(BorrowEvent {
+                cell: cell,
+                kind: kind,
+            });
+        }
+    });
+}
+
+#[cfg(ndebug)]
+#[inline(always)]
+fn report_borrow(_: uint, _: BorrowEventKind) {
+}
+
+/// A failed `DOMRefCell` borrow that is about to panic, as reported to the
+/// hook registered with `set_borrow_panic_hook`.
+#[derive(Copy, Clone, Show)]
+pub struct BorrowPanicInfo {
+    /// The address of the cell.
+    pub cell: uint,
+    /// The kind of borrow that was attempted.
+    pub kind: BorrowEventKind,
+    /// The name of the field, for `borrow_named` and `borrow_mut_named`.
+    pub field_name: Option<&'static str>,
+}
+
+thread_local!(static BORROW_PANIC_HOOK: Cell<Option<fn(BorrowPanicInfo)>> = Cell::new(None));
+
+/// Registers `hook` to be called on the current thread whenever a
+/// `DOMRefCell` borrow is about to panic because the value is already
+/// borrowed, replacing any previously registered hook.
+///
+/// The hook runs before unwinding starts, so it is called even if the panic
+/// is later caught. Unlike `set_borrow_hook`, this works in release builds
+/// too.
+pub fn set_borrow_panic_hook(hook: fn(BorrowPanicInfo)) {
+    BORROW_PANIC_HOOK.with(|h| h.set(Some(hook)));
+}
+
+/// Unregisters the hook registered with `set_borrow_panic_hook`, if any.
+pub fn clear_borrow_panic_hook() {
+    BORROW_PANIC_HOOK.with(|h| h.set(None));
+}
+
+fn report_borrow_panic(cell: uint, kind: BorrowEventKind, field_name: Option<&'static str>) {
+    BORROW_PANIC_HOOK.with(|h| {
+        if let Some(hook) = h.get() {
+            hook(BorrowPanicInfo {
+                cell: cell,
+                kind: kind,
+                field_name: field_name,
+            });
+        }
+    });
+}
+
+/// Logs a failed checked borrow of the cell at address `cell`.
+///
+/// This uses `log!` at the debug level directly, since `debug!` is compiled
+/// out of release builds.
+#[cfg(feature = "borrow-logging")]
+fn log_borrow_conflict(cell: uint, reason: BorrowFailure) {
+    log!(::log::DEBUG, "DOMRefCell<T> at {:x} could not be borrowed: {:?}", cell, reason);
+}
+
+#[cfg(not(feature = "borrow-logging"))]
+#[inline(always)]
+fn log_borrow_conflict(_: uint, _: BorrowFailure) {
+}
+
+#[cfg(not(ndebug))]
+thread_local!(static BORROW_WATCHDOG: Cell<Option<u64>> = Cell::new(None));
+
+/// Enables the borrow watchdog on the current thread: from now on,
+/// `DOMRefCell::check_borrow_watchdog` warns about mutable borrows that have
+/// been held for longer than `threshold`.
+///
+/// Enabling the watchdog makes every mutable borrow read the clock. In
+/// release builds, this does nothing.
+#[cfg(not(ndebug))]
+pub fn set_borrow_watchdog(threshold: Duration) {
+    let threshold = threshold.num_milliseconds() as u64 * 1000000;
+    BORROW_WATCHDOG.with(|w| w.set(Some(threshold)));
+}
+
+#[cfg(ndebug)]
+#[inline(always)]
+pub fn set_borrow_watchdog(_: Duration) {
+}
+
+/// Disables the borrow watchdog on the current thread.
+#[cfg(not(ndebug))]
+pub fn clear_borrow_watchdog() {
+    BORROW_WATCHDOG.with(|w| w.set(None));
+}
+
+#[cfg(ndebug)]
+#[inline(always)]
+pub fn clear_borrow_watchdog() {
+}
+
+#[cfg(not(ndebug))]
+fn borrow_watchdog_threshold() -> Option<u64> {
+    BORROW_WATCHDOG.with(|w| w.get())
+}
+
+#[cfg(ndebug)]
+#[inline(always)]
+fn borrow_watchdog_threshold() -> Option<u64> {
+    None
+}
+
+/// The total time borrows taken with `DOMRefCell::borrow_timed` on this
+/// thread were held for, in nanoseconds, keyed by the type of the value.
+#[cfg(not(ndebug))]
+thread_local!(static BORROW_TIMES: RefCell<HashMap<TypeId, u64>> = RefCell::new(HashMap::new()));
+
+/// The mutable borrows leaked by `DOMRefCell::borrow_mut_leak` on this
+/// thread, keyed by the address of the cell, each with the function that
+/// releases it.
+thread_local!(static LEAKED_BORROWS: RefCell<HashMap<uint, (*mut (), unsafe fn(*mut ()))>>
+              = RefCell::new(HashMap::new()));
+
+/// Releases a leaked `RefMut<T>`, boxed and cast to `*mut ()`.
+unsafe fn release_leaked_borrow<T>(guard: *mut ()) {
+    let guard: Box<RefMut<T>> = mem::transmute(guard);
+    drop(guard);
+}
+
+/// Proof that the current thread is the script thread.
+///
+/// Holding one of these lets `DOMRefCell::borrow_with_token` and
+/// `DOMRefCell::borrow_mut_with_token` skip the task state check, which
+/// matters in borrow-heavy loops. It cannot be sent to another thread.
+pub struct ScriptThreadToken {
+    _nosend: marker::NoSend,
+}
+
+impl ScriptThreadToken {
+    /// Create a token for the current thread.
+    ///
+    /// The caller must be on the script thread. This is meant to be called
+    /// once, at the top of the script event loop.
+    pub unsafe fn assume_script() -> ScriptThreadToken {
+        debug_assert!(task_state::get().is_script());
+        ScriptThreadToken {
+            _nosend: marker::NoSend,
+        }
+    }
+}
+
+/// A `Ref` projected onto part of the borrowed value.
+///
+/// Returned by `DOMRefCell::borrow_map`. The borrow lasts until the
+/// `MappedRef` exits scope.
+pub struct MappedRef<'a, T: 'a, U: ?Sized + 'a> {
+    _guard: Ref<'a, T>,
+    value: *const U,
+}
+
+impl<'a, T, U: ?Sized> Deref for MappedRef<'a, T, U> {
+    type Target = U;
+    #[allow(unsafe_blocks)]
+    fn deref<'b>(&'b self) -> &'b U {
+        // The pointer was derived from the value `_guard` keeps borrowed.
+        unsafe {
+            &*self.value
+        }
+    }
+}
+
+/// A `RefMut` projected onto part of the borrowed value.
+///
+/// Returned by `DOMRefCell::borrow_mut_map`. The borrow lasts until the
+/// `MappedRefMut` exits scope.
+pub struct MappedRefMut<'a, T: 'a, U: ?Sized + 'a> {
+    _guard: RefMut<'a, T>,
+    value: *mut U,
+}
+
+impl<'a, T, U: ?Sized> Deref for MappedRefMut<'a, T, U> {
+    type Target = U;
+    #[allow(unsafe_blocks)]
+    fn deref<'b>(&'b self) -> &'b U {
+        // The pointer was derived from the value `_guard` keeps borrowed.
+        unsafe {
+            &*self.value
+        }
+    }
+}
+
+impl<'a, T, U: ?Sized> DerefMut for MappedRefMut<'a, T, U> {
+    #[allow(unsafe_blocks)]
+    fn deref_mut<'b>(&'b mut self) -> &'b mut U {
+        // The pointer was derived from the value `_guard` keeps borrowed.
+        unsafe {
+            &mut *self.value
+        }
+    }
+}
+
+/// The result of `DOMRefCell::try_borrow` or `DOMRefCell::try_borrow_mut`:
+/// the guard, or nothing if the value was busy.
+///
+/// This is an `Option` that has to be looked at: a `cell.try_borrow();`
+/// that drops the result unchecked is linted. Use `into_option` to match
+/// on it.
+#[must_use = "borrow result must be checked"]
+pub struct TryBorrow<G>(Option<G>);
+
+impl<G> TryBorrow<G> {
+    /// Returns the guard, if the borrow succeeded.
+    #[inline]
+    pub fn into_option(self) -> Option<G> {
+        self.0
+    }
+
+    /// Did the borrow succeed?
+    #[inline]
+    pub fn is_some(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Did the borrow fail?
+    #[inline]
+    pub fn is_none(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Calls `f` with the guard, if the borrow succeeded.
+    #[inline]
+    pub fn map<U, F: FnOnce(G) -> U>(self, f: F) -> Option<U> {
+        self.0.map(f)
+    }
+}
+
+/// A mutable borrow of a `DOMRefCell`, returned by
+/// `DOMRefCell::borrow_mut_wrapped`.
+///
+/// This behaves like a `RefMut`, but is a local type, so DOM code can wrap
+/// it in guards of its own and implement traits for it.
+pub struct Guard<'a, T: 'a>(RefMut<'a, T>);
+
+impl<'a, T> Deref for Guard<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+        &*self.0
+    }
+}
+
+impl<'a, T> DerefMut for Guard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T {
+        &mut *self.0
+    }
+}
+
+/// One of the two halves of a `RefMut` split by
+/// `DOMRefCell::borrow_mut_split`.
+///
+/// The halves share the original borrow, which lasts until both of them
+/// exit scope.
+pub struct SplitRefMut<'a, T: 'a, U: ?Sized + 'a> {
+    _guard: Rc<RefMut<'a, T>>,
+    value: *mut U,
+}
+
+impl<'a, T, U: ?Sized> Deref for SplitRefMut<'a, T, U> {
+    type Target = U;
+    #[allow(unsafe_blocks)]
+    fn deref<'b>(&'b self) -> &'b U {
+        // The pointer was derived from the value `_guard` keeps borrowed.
+        unsafe {
+            &*self.value
+        }
+    }
+}
+
+impl<'a, T, U: ?Sized> DerefMut for SplitRefMut<'a, T, U> {
+    #[allow(unsafe_blocks)]
+    fn deref_mut<'b>(&'b mut self) -> &'b mut U {
+        // The pointer was derived from the value `_guard` keeps borrowed, and
+        // the two halves point to disjoint parts of it.
+        unsafe {
+            &mut *self.value
+        }
+    }
+}
+
+/// A handle that can repeatedly borrow a `DOMRefCell` without itself
+/// holding a borrow.
+///
+/// Returned by `DOMRefCell::refreshable_borrow`. The task state is checked
+/// once, when the handle is created, rather than on every `get`.
+pub struct RefreshableRef<'a, T: 'a> {
+    cell: &'a DOMRefCell<T>,
+}
+
+impl<'a, T> RefreshableRef<'a, T> {
+    /// Immutably borrows the wrapped value until the returned `Ref` exits
+    /// scope.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn get(&self) -> Ref<'a, T> {
+        match self.cell.raw_try_borrow() {
+            Some(ptr) => ptr,
+            None => {
+                self.cell.borrow_panicked(BorrowEventKind::Shared, None);
+                panic!("DOMRefCell<T> already mutably borrowed")
+            }
+        }
+    }
+}
+
+/// An immutable borrow of a `DOMRefCell` meant to be read many times.
+///
+/// Returned by `DOMRefCell::borrow_many`. The task state is checked and the
+/// borrow taken once, when the lease is created; `get` is then free. The
+/// borrow lasts until the lease exits scope.
+pub struct BorrowLease<'a, T: 'a> {
+    guard: Ref<'a, T>,
+}
+
+impl<'a, T> BorrowLease<'a, T> {
+    /// Returns a reference to the borrowed value.
+    #[inline]
+    pub fn get<'b>(&'b self) -> &'b T {
+        &*self.guard
+    }
+}
+
+impl<'a, T> Deref for BorrowLease<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+        self.get()
+    }
+}
+
+/// A read-only view of a `DOMRefCell`.
+///
+/// Returned by `DOMRefCell::as_readonly`, to give code the ability to read
+/// a field but not to mutate it.
+pub struct ReadOnlyCell<'a, T: 'a> {
+    cell: &'a DOMRefCell<T>,
+}
+
+impl<'a, T> ReadOnlyCell<'a, T> {
+    /// Immutably borrows the wrapped value. See `DOMRefCell::borrow`.
+    pub fn borrow(&self) -> Ref<'a, T> {
+        self.cell.borrow()
+    }
+
+    /// Attempts to immutably borrow the wrapped value. See
+    /// `DOMRefCell::try_borrow`.
+    pub fn try_borrow(&self) -> TryBorrow<Ref<'a, T>> {
+        self.cell.try_borrow()
+    }
+
+    /// Immutably borrows the wrapped value for the duration of `f`. See
+    /// `DOMRefCell::with`.
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        self.cell.with(f)
+    }
+}
+
+/// An immutable borrow of a `DOMRefCell` that records how long it was held.
+///
+/// Returned by `DOMRefCell::borrow_timed`. In debug builds, the time is
+/// added to the total for `T` when this exits scope; see
+/// `DOMRefCell::total_borrow_time`.
+pub struct TimedRef<'a, T: 'static> {
+    guard: Ref<'a, T>,
+    #[cfg(not(ndebug))]
+    borrowed_at: u64,
+}
+
+impl<'a, T: 'static> Deref for TimedRef<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+        &*self.guard
+    }
+}
+
+#[cfg(not(ndebug))]
+#[unsafe_destructor]
+impl<'a, T: 'static> Drop for TimedRef<'a, T> {
+    fn drop(&mut self) {
+        let held = time::precise_time_ns() - self.borrowed_at;
+        BORROW_TIMES.with(|times| {
+            let mut times = times.borrow_mut();
+            let key = TypeId::of::<T>();
+            let total = times.get(&key).map_or(0, |total| *total);
+            times.insert(key, total + held);
+        });
+    }
 }
 
 // Functionality specific to Servo's `DOMRefCell` type
@@ -32,6 +626,56 @@ impl<T> DOMRefCell<T> {
         &*self.value.as_unsafe_cell().get()
     }
 
+    /// Return a mutable reference to the contents.
+    ///
+    /// For use in the layout task only, for short writes such as caching a
+    /// computed value. No other reference to the contents, whether from
+    /// `borrow_for_layout` or from another call to this method, may be alive
+    /// while the returned reference is used.
+    pub unsafe fn borrow_for_layout_mut<'a>(&'a self) -> &'a mut T {
+        debug_assert!(task_state::get().is_layout());
+        &mut *self.value.as_unsafe_cell().get()
+    }
+
+    /// Return a reference to the contents, unless the cell is currently
+    /// mutably borrowed.
+    ///
+    /// For use in the layout task only. Unlike `borrow_for_layout`, this
+    /// looks at the borrow flag, so it does not hand out a reference that
+    /// aliases a mutable borrow taken before the call. It holds no borrow,
+    /// though, so the same rules as for `borrow_for_layout` apply to the
+    /// returned reference: script must not mutate the cell while it is
+    /// alive. Checking the flag is only sound while script is not running.
+    pub unsafe fn try_borrow_for_layout<'a>(&'a self) -> Option<&'a T> {
+        debug_assert!(task_state::get().is_layout());
+        match self.value.try_borrow() {
+            Some(_) => Some(&*self.value.as_unsafe_cell().get()),
+            None => None,
+        }
+    }
+
+    /// Return a reference to the contents, reinterpreted as a `U`.
+    ///
+    /// For use in the layout task only, like `borrow_for_layout`. The caller
+    /// must ensure that `U` is layout-compatible with `T`: the same size and
+    /// alignment, with every field of `U` at the offset of a field of `T` of
+    /// a compatible type, as with a `#[repr(C)]` newtype. Both types should
+    /// be `#[repr(C)]`, since the layout of other structs is unspecified. Only
+    /// the size is checked, in debug builds.
+    pub unsafe fn borrow_as<'a, U>(&'a self) -> &'a U {
+        debug_assert!(task_state::get().is_layout());
+        debug_assert!(mem::size_of::<T>() == mem::size_of::<U>());
+        &*(self.value.as_unsafe_cell().get() as *const U)
+    }
+
+    /// Return a reference to the contents.
+    ///
+    /// For use in the paint task only.
+    pub unsafe fn borrow_for_paint<'a>(&'a self) -> &'a T {
+        debug_assert!(task_state::get().is_paint());
+        &*self.value.as_unsafe_cell().get()
+    }
+
     /// Borrow the contents for the purpose of GC tracing.
     ///
     /// This succeeds even if the object is mutably borrowed,
@@ -41,6 +685,36 @@ impl<T> DOMRefCell<T> {
         &*self.value.as_unsafe_cell().get()
     }
 
+    /// Borrow the contents for the purpose of GC tracing, unless they are
+    /// currently mutably borrowed.
+    ///
+    /// Unlike `borrow_for_gc_trace`, this never returns a reference to a
+    /// value that is in the middle of being mutated, at the cost of leaving
+    /// it untraced. Only use it for fields where skipping the trace is known
+    /// to be safe.
+    pub unsafe fn try_borrow_for_gc_trace<'a>(&'a self) -> Option<&'a T> {
+        debug_assert!(task_state::get().contains(SCRIPT | IN_GC));
+        match self.value.try_borrow() {
+            Some(_) => Some(&*self.value.as_unsafe_cell().get()),
+            None => None,
+        }
+    }
+
+    /// Return a reference to the contents without holding a borrow, unless
+    /// the cell is currently mutably borrowed.
+    ///
+    /// Unlike `try_borrow`, no `Ref` guard is created, so the cell is not
+    /// marked as borrowed while the returned reference is alive. The caller
+    /// must ensure that the reference does not outlive the next mutable
+    /// borrow of the cell.
+    pub unsafe fn try_borrow_unguarded<'a>(&'a self) -> Option<&'a T> {
+        debug_assert!(task_state::get().is_script());
+        match self.value.try_borrow() {
+            Some(_) => Some(&*self.value.as_unsafe_cell().get()),
+            None => None,
+        }
+    }
+
     /// Borrow the contents for the purpose of script deallocation.
     ///
     pub unsafe fn borrow_for_script_deallocation<'a>(&'a self) -> &'a mut T {
@@ -48,75 +722,269 @@ impl<T> DOMRefCell<T> {
         &mut *self.value.as_unsafe_cell().get()
     }
 
-    /// Is the cell mutably borrowed?
+    /// Mutably borrow the contents, ignoring any outstanding borrows.
     ///
-    /// For safety checks in debug builds only.
-    pub fn is_mutably_borrowed(&self) -> bool {
-        self.value.try_borrow().is_some()
+    /// This is for reentrant algorithms with a single logical owner, where
+    /// the same call stack may re-enter while an earlier reference obtained
+    /// from this method is still alive. The caller must ensure that no two
+    /// such references are ever used to access the value at the same time,
+    /// and that no `Ref` or `RefMut` is alive while they are used.
+    pub unsafe fn borrow_mut_reentrant<'a>(&'a self) -> &'a mut T {
+        debug_assert!(task_state::get().is_script());
+        &mut *self.value.as_unsafe_cell().get()
     }
 
-    /// Attempts to immutably borrow the wrapped value.
+    /// Mutably borrow the contents to let `populate` fill them in, then
+    /// return a shared reference to them that holds no borrow.
     ///
-    /// The borrow lasts until the returned `Ref` exits scope. Multiple
-    /// immutable borrows can be taken out at the same time.
-    ///
-    /// Returns `None` if the value is currently mutably borrowed.
+    /// This is for caches that are filled once and then read for the rest of
+    /// a frame. The caller must ensure that no mutable borrow of the cell is
+    /// taken, through any method, for as long as the returned reference is
+    /// alive; shared borrows are fine. A `borrow_mut` during that time is not
+    /// caught, and would alias the returned reference.
     ///
     /// # Panics
     ///
     /// Panics if this is called off the script thread.
-    pub fn try_borrow<'a>(&'a self) -> Option<Ref<'a, T>> {
-        debug_assert!(task_state::get().is_script());
-        self.value.try_borrow()
+    ///
+    /// Panics if the value is currently borrowed.
+    pub unsafe fn borrow_mut_then_freeze<'a, F>(&'a self, populate: F) -> &'a T
+        where F: FnOnce(&mut T)
+    {
+        populate(&mut *self.borrow_mut());
+        &*self.value.as_unsafe_cell().get()
     }
 
-    /// Mutably borrows the wrapped value.
+    /// Mutably borrow the contents and keep them borrowed after this returns,
+    /// for handing the value to code outside Rust's control, such as the JS
+    /// engine.
     ///
-    /// The borrow lasts until the returned `RefMut` exits scope. The value
-    /// cannot be borrowed while this borrow is active.
+    /// The cell stays mutably borrowed until `reclaim_leaked_mut` is called.
+    /// Dereferencing the returned pointer is only sound until then.
     ///
-    /// Returns `None` if the value is currently borrowed.
+    /// The leaked borrow is looked up by the address of the cell, so the
+    /// caller must ensure that the cell is neither moved nor dropped before
+    /// `reclaim_leaked_mut` is called: a moved cell could never be reclaimed,
+    /// and a dropped one would leave a dangling guard behind.
     ///
     /// # Panics
     ///
     /// Panics if this is called off the script thread.
-    pub fn try_borrow_mut<'a>(&'a self) -> Option<RefMut<'a, T>> {
-        debug_assert!(task_state::get().is_script());
-        self.value.try_borrow_mut()
+    ///
+    /// Panics if the value is currently borrowed.
+    pub unsafe fn borrow_mut_leak(&self) -> *mut T {
+        let guard = self.borrow_mut();
+        let guard: *mut () = mem::transmute(box guard);
+        let release = release_leaked_borrow::<T> as unsafe fn(*mut ());
+        LEAKED_BORROWS.with(|leaked| {
+            leaked.borrow_mut().insert(self as *const DOMRefCell<T> as uint, (guard, release));
+        });
+        self.value.as_unsafe_cell().get()
     }
-}
 
-impl<T: JSTraceable> JSTraceable for DOMRefCell<T> {
-    fn trace(&self, trc: *mut JSTracer) {
-        (*self).borrow().trace(trc)
+    /// Release the borrow taken by `borrow_mut_leak`.
+    ///
+    /// The caller must ensure that the pointer returned by `borrow_mut_leak`
+    /// is no longer used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cell has no leaked borrow.
+    pub unsafe fn reclaim_leaked_mut(&self) {
+        let entry = LEAKED_BORROWS.with(|leaked| {
+            leaked.borrow_mut().remove(&(self as *const DOMRefCell<T> as uint))
+        });
+        match entry {
+            Some((guard, release)) => release(guard),
+            None => panic!("DOMRefCell<T> has no leaked borrow"),
+        }
     }
-}
 
-// Functionality duplicated with `core::cell::RefCell`
-// ===================================================
-impl<T> DOMRefCell<T> {
-    /// Create a new `DOMRefCell` containing `value`.
-    pub fn new(value: T) -> DOMRefCell<T> {
-        DOMRefCell {
-            value: RefCell::new(value),
-        }
+    /// Is the cell borrowed by `borrow_mut_leak`?
+    ///
+    /// For teardown checks.
+    pub fn has_leaked_borrow(&self) -> bool {
+        LEAKED_BORROWS.with(|leaked| {
+            leaked.borrow().contains_key(&(self as *const DOMRefCell<T> as uint))
+        })
     }
 
+    /// Return a raw pointer to the contents, without any borrow bookkeeping.
+    ///
+    /// Dereferencing the pointer is only sound if it does not alias a live
+    /// `Ref` or `RefMut` in a way that violates the usual borrowing rules;
+    /// ensuring that is up to the caller.
+    pub fn as_ptr(&self) -> *mut T {
+        debug_assert!(task_state::get().is_script());
+        self.value.as_unsafe_cell().get()
+    }
 
-    /// Immutably borrows the wrapped value.
+    /// Do `a` and `b` refer to the same cell?
     ///
-    /// The borrow lasts until the returned `Ref` exits scope. Multiple
+    /// This compares addresses only and never borrows either cell.
+    pub fn ptr_eq(a: &DOMRefCell<T>, b: &DOMRefCell<T>) -> bool {
+        a.value.as_unsafe_cell().get() == b.value.as_unsafe_cell().get()
+    }
+
+    /// Is the cell mutably borrowed?
+    ///
+    /// For safety checks in debug builds only.
+    pub fn is_mutably_borrowed(&self) -> bool {
+        self.value.try_borrow().is_none()
+    }
+
+    /// Is the cell immutably borrowed (and not mutably borrowed)?
+    ///
+    /// For safety checks in debug builds only.
+    pub fn is_immutably_borrowed(&self) -> bool {
+        self.value.try_borrow_mut().is_none() && self.value.try_borrow().is_some()
+    }
+
+    /// Returns the current borrow state of the cell.
+    ///
+    /// For assertions in tests and debug builds.
+    pub fn borrow_state(&self) -> BorrowState {
+        if self.value.try_borrow_mut().is_some() {
+            BorrowState::Unborrowed
+        } else if self.value.try_borrow().is_some() {
+            BorrowState::Shared
+        } else {
+            BorrowState::Mutable
+        }
+    }
+
+    /// Freezes the cell, so that in debug builds any later attempt to borrow
+    /// it mutably fails.
+    ///
+    /// This is for write-once fields, set during construction and only read
+    /// afterwards. It is a debug-only assertion, like the task state checks:
+    /// the flag lives in the per-cell diagnostics, which are empty in release
+    /// builds so that cells stay as small as a `RefCell`. In release builds
+    /// this does nothing, and a frozen cell can still be borrowed mutably.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn freeze(&self) {
+        debug_assert!(task_state::get().is_script());
+        self.diagnostics.set_frozen(true);
+    }
+
+    /// Returns whether the cell has been frozen with `freeze`, in debug
+    /// builds.
+    ///
+    /// Freezing is not tracked in release builds, where this always returns
+    /// `false`. Only use it in assertions.
+    pub fn is_frozen(&self) -> bool {
+        self.diagnostics.frozen()
+    }
+
+    /// Unfreezes the cell, so that it can be borrowed mutably again in debug
+    /// builds, e.g. when resetting a document for reuse.
+    ///
+    /// Like `freeze`, this does nothing in release builds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn unfreeze(&self) {
+        debug_assert!(task_state::get().is_script());
+        self.diagnostics.set_frozen(false);
+    }
+
+    /// Warns if the cell has been mutably borrowed for longer than the
+    /// threshold passed to `set_borrow_watchdog`, and returns whether it did.
+    ///
+    /// Call this periodically on cells suspected of holding on to a
+    /// `RefMut` for too long. Only mutable borrows taken while the watchdog
+    /// was enabled are timed. In release builds, this does nothing.
+    pub fn check_borrow_watchdog(&self) -> bool {
+        let threshold = match borrow_watchdog_threshold() {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        if self.borrow_state() != BorrowState::Mutable {
+            return false;
+        }
+        let borrowed_at = match self.diagnostics.mutably_borrowed_at() {
+            Some(borrowed_at) => borrowed_at,
+            None => return false,
+        };
+        let held = time::precise_time_ns() - borrowed_at;
+        if held <= threshold {
+            return false;
+        }
+        warn!("DOMRefCell<T> at {:x} mutably borrowed for {}ms",
+              self as *const DOMRefCell<T> as uint, held / 1000000);
+        true
+    }
+
+    /// Borrows the underlying `RefCell` without checking the task state,
+    /// reporting successful borrows to the borrow hook.
+    fn raw_try_borrow<'a>(&'a self) -> Option<Ref<'a, T>> {
+        let borrow = self.value.try_borrow();
+        if borrow.is_some() {
+            report_borrow(self as *const DOMRefCell<T> as uint, BorrowEventKind::Shared);
+        }
+        borrow
+    }
+
+    /// Reports a borrow that is about to panic to the borrow panic hook.
+    fn borrow_panicked(&self, kind: BorrowEventKind, field_name: Option<&'static str>) {
+        report_borrow_panic(self as *const DOMRefCell<T> as uint, kind, field_name);
+    }
+
+    /// Mutably borrows the underlying `RefCell` without checking the task
+    /// state, reporting successful borrows to the borrow hook. Fails if the
+    /// cell is frozen.
+    fn raw_try_borrow_mut<'a>(&'a self) -> Option<RefMut<'a, T>> {
+        if self.diagnostics.frozen() {
+            return None;
+        }
+        let borrow = self.value.try_borrow_mut();
+        if borrow.is_some() {
+            report_borrow(self as *const DOMRefCell<T> as uint, BorrowEventKind::Mutable);
+            // Always overwrite the timestamp, so that a borrow taken while
+            // the watchdog is disabled is not timed from an earlier one.
+            let now = borrow_watchdog_threshold().map(|_| time::precise_time_ns());
+            self.diagnostics.set_mutably_borrowed_at(now);
+        }
+        borrow
+    }
+
+    /// Attempts to immutably borrow the wrapped value.
+    ///
+    /// The borrow lasts until the returned `Ref` exits scope. Multiple
     /// immutable borrows can be taken out at the same time.
     ///
+    /// Returns nothing if the value is currently mutably borrowed. Use
+    /// `try_borrow_checked` to find out why the borrow failed.
+    ///
     /// # Panics
     ///
     /// Panics if this is called off the script thread.
+    pub fn try_borrow<'a>(&'a self) -> TryBorrow<Ref<'a, T>> {
+        debug_assert!(task_state::get().is_script());
+        TryBorrow(self.try_borrow_checked().ok())
+    }
+
+    /// Attempts to immutably borrow the wrapped value, reporting why the
+    /// borrow failed.
     ///
-    /// Panics if the value is currently mutably borrowed.
-    pub fn borrow<'a>(&'a self) -> Ref<'a, T> {
-        match self.try_borrow() {
-            Some(ptr) => ptr,
-            None => panic!("DOMRefCell<T> already mutably borrowed")
+    /// Unlike `try_borrow`, this does not assert that it is called on the
+    /// script thread; in debug builds, a call off the script thread is
+    /// reported as `BorrowFailure::WrongTaskState` instead.
+    pub fn try_borrow_checked<'a>(&'a self) -> Result<Ref<'a, T>, BorrowError> {
+        if cfg!(not(ndebug)) && !task_state::get().is_script() {
+            return Err(BorrowError { reason: BorrowFailure::WrongTaskState });
+        }
+        match self.raw_try_borrow() {
+            Some(ptr) => Ok(ptr),
+            None => {
+                let reason = BorrowFailure::AlreadyMutablyBorrowed;
+                log_borrow_conflict(self as *const DOMRefCell<T> as uint, reason);
+                Err(BorrowError { reason: reason })
+            }
         }
     }
 
@@ -125,15 +993,2778 @@ impl<T> DOMRefCell<T> {
     /// The borrow lasts until the returned `RefMut` exits scope. The value
     /// cannot be borrowed while this borrow is active.
     ///
+    /// Returns nothing if the value is currently borrowed. Use
+    /// `try_borrow_mut_checked` to find out why the borrow failed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn try_borrow_mut<'a>(&'a self) -> TryBorrow<RefMut<'a, T>> {
+        debug_assert!(task_state::get().is_script());
+        TryBorrow(self.try_borrow_mut_checked().ok())
+    }
+
+    /// Attempts to mutably borrow the wrapped value, reporting why the
+    /// borrow failed.
+    ///
+    /// Unlike `try_borrow_mut`, this does not assert that it is called on the
+    /// script thread; in debug builds, a call off the script thread is
+    /// reported as `BorrowFailure::WrongTaskState` instead.
+    pub fn try_borrow_mut_checked<'a>(&'a self) -> Result<RefMut<'a, T>, BorrowMutError> {
+        if cfg!(not(ndebug)) && !task_state::get().is_script() {
+            return Err(BorrowMutError { reason: BorrowFailure::WrongTaskState });
+        }
+        match self.raw_try_borrow_mut() {
+            Some(ptr) => Ok(ptr),
+            None => {
+                let reason = if self.diagnostics.frozen() {
+                    BorrowFailure::Frozen
+                } else {
+                    BorrowFailure::AlreadyBorrowed
+                };
+                log_borrow_conflict(self as *const DOMRefCell<T> as uint, reason);
+                Err(BorrowMutError { reason: reason })
+            }
+        }
+    }
+
+    /// Attempts to mutably borrow the wrapped value, logging a warning that
+    /// mentions `context` and the `BorrowFailure` if the borrow fails.
+    ///
+    /// This is for best-effort work that should be skipped, rather than
+    /// panic, when the value is busy. Like `try_borrow_mut_checked`, this
+    /// does not assert that it is called on the script thread.
+    pub fn try_borrow_mut_or_log<'a>(&'a self, context: &str) -> Option<RefMut<'a, T>> {
+        match self.try_borrow_mut_checked() {
+            Ok(borrow) => Some(borrow),
+            Err(error) => {
+                warn!("{}: DOMRefCell<T> could not be borrowed: {:?}", context, error.reason);
+                None
+            }
+        }
+    }
+
+    /// Attempts to mutably borrow the wrapped value, giving up for good once
+    /// `budget` attempts have been blocked.
+    ///
+    /// In debug builds, each call that fails because the value is borrowed
+    /// counts against the budget; once it is exhausted, this returns `None`
+    /// without trying, until `reset_blocked_attempts` is called (e.g. at the
+    /// start of the next frame). This is meant to surface pathological
+    /// reentrancy. In release builds, blocked attempts are not counted.
+    ///
     /// # Panics
     ///
     /// Panics if this is called off the script thread.
+    pub fn try_borrow_mut_attempts<'a>(&'a self, budget: u32) -> Option<RefMut<'a, T>> {
+        let blocked = self.diagnostics.blocked_attempts();
+        if blocked >= budget {
+            return None;
+        }
+        let borrow = self.try_borrow_mut().into_option();
+        if borrow.is_none() {
+            self.diagnostics.set_blocked_attempts(blocked + 1);
+        }
+        borrow
+    }
+
+    /// Resets the count of blocked `try_borrow_mut_attempts` calls.
+    pub fn reset_blocked_attempts(&self) {
+        self.diagnostics.set_blocked_attempts(0);
+    }
+
+    /// Immutably borrows the wrapped value, like `borrow`, but without
+    /// checking the task state: `token` proves we are on the script thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow_with_token<'a>(&'a self, _token: &ScriptThreadToken) -> Ref<'a, T> {
+        match self.raw_try_borrow() {
+            Some(ptr) => ptr,
+            None => {
+                self.borrow_panicked(BorrowEventKind::Shared, None);
+                panic!("DOMRefCell<T> already mutably borrowed")
+            }
+        }
+    }
+
+    /// Mutably borrows the wrapped value, like `borrow_mut`, but without
+    /// checking the task state: `token` proves we are on the script thread.
+    ///
+    /// # Panics
     ///
     /// Panics if the value is currently borrowed.
-    pub fn borrow_mut<'a>(&'a self) -> RefMut<'a, T> {
-        match self.try_borrow_mut() {
+    pub fn borrow_mut_with_token<'a>(&'a self, _token: &ScriptThreadToken) -> RefMut<'a, T> {
+        match self.raw_try_borrow_mut() {
+            Some(ptr) => ptr,
+            None => {
+                self.borrow_panicked(BorrowEventKind::Mutable, None);
+                panic!("DOMRefCell<T> already borrowed")
+            }
+        }
+    }
+
+    /// Immutably borrows the wrapped value, like `borrow`, naming the field
+    /// (e.g. `"Node.childNodes"`) in the panic message if it fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow_named<'a>(&'a self, field_name: &'static str) -> Ref<'a, T> {
+        match self.try_borrow().into_option() {
+            Some(ptr) => ptr,
+            None => {
+                self.borrow_panicked(BorrowEventKind::Shared, Some(field_name));
+                panic!("DOMRefCell for {} already mutably borrowed", field_name)
+            }
+        }
+    }
+
+    /// Mutably borrows the wrapped value, like `borrow_mut`, naming the field
+    /// (e.g. `"Node.childNodes"`) in the panic message if it fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut_named<'a>(&'a self, field_name: &'static str) -> RefMut<'a, T> {
+        match self.try_borrow_mut().into_option() {
             Some(ptr) => ptr,
-            None => panic!("DOMRefCell<T> already borrowed")
+            None => {
+                self.borrow_panicked(BorrowEventKind::Mutable, Some(field_name));
+                panic!("DOMRefCell for {} already borrowed", field_name)
+            }
+        }
+    }
+
+    /// Returns a handle that borrows the wrapped value on demand.
+    ///
+    /// Use this in loops that need to release the borrow between iterations,
+    /// so that GC or reentrant script can run in between.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn refreshable_borrow<'a>(&'a self) -> RefreshableRef<'a, T> {
+        debug_assert!(task_state::get().is_script());
+        RefreshableRef {
+            cell: self,
+        }
+    }
+
+    /// Returns a read-only view of the cell.
+    pub fn as_readonly<'a>(&'a self) -> ReadOnlyCell<'a, T> {
+        ReadOnlyCell {
+            cell: self,
+        }
+    }
+
+    /// Immutably borrows the wrapped value once, for repeated access in a
+    /// loop. See `BorrowLease`.
+    ///
+    /// Unlike `refreshable_borrow`, the value stays borrowed between
+    /// iterations.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow_many<'a>(&'a self) -> BorrowLease<'a, T> {
+        BorrowLease {
+            guard: self.borrow(),
+        }
+    }
+
+    /// Immutably borrows the wrapped value for the duration of `f`.
+    ///
+    /// The borrow is released before this returns, so it cannot accidentally
+    /// outlive the call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        f(&*self.borrow())
+    }
+
+    /// Immutably borrows the wrapped value for the duration of `f`, like
+    /// `with`, for debugging and assertion code that only looks at it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    #[inline(always)]
+    pub fn inspect<F: FnOnce(&T)>(&self, f: F) {
+        f(&*self.borrow())
+    }
+
+    /// Mutably borrows the wrapped value for the duration of `f`.
+    ///
+    /// The borrow is released before this returns, so it cannot accidentally
+    /// outlive the call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        f(&mut *self.borrow_mut())
+    }
+
+    /// Mutably borrows the wrapped value once and applies each of `ops` to it
+    /// in order, so no other code can observe the value between two of them.
+    ///
+    /// The borrow is held for the whole batch: the operations must not borrow
+    /// this cell themselves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut_batch<F, I>(&self, ops: I)
+        where F: FnMut(&mut T), I: Iterator<Item=F>
+    {
+        let mut value = self.borrow_mut();
+        for mut op in ops {
+            op(&mut *value);
+        }
+    }
+
+    /// Attempts to immutably borrow the wrapped value for the duration of
+    /// `f`.
+    ///
+    /// Returns `None` without calling `f` if the value is currently mutably
+    /// borrowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn try_with<R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.try_borrow().map(|value| f(&*value))
+    }
+
+    /// Attempts to mutably borrow the wrapped value for the duration of `f`.
+    ///
+    /// Returns `None` without calling `f` if the value is currently borrowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn try_with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+        self.try_borrow_mut().map(|mut value| f(&mut *value))
+    }
+
+    /// Calls `on_ok` with an immutable borrow of the wrapped value, or
+    /// `on_busy` if the value is currently mutably borrowed.
+    ///
+    /// Exactly one of the two closures is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn borrow_or_else<R, F, G>(&self, on_ok: F, on_busy: G) -> R
+        where F: FnOnce(&T) -> R, G: FnOnce() -> R
+    {
+        match self.try_borrow().into_option() {
+            Some(value) => on_ok(&*value),
+            None => on_busy(),
+        }
+    }
+
+    /// Releases `guard`, a mutable borrow of this cell, for the duration of
+    /// `f`, then borrows the value mutably again.
+    ///
+    /// Returns the new guard along with the result of `f`. While `f` runs the
+    /// cell is unborrowed, so `f` may borrow it itself; any reference derived
+    /// from the old guard must not be kept across this call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is still borrowed when `f` returns.
+    pub fn scoped_unborrow<'a, R, F>(&'a self, guard: RefMut<'a, T>, f: F)
+                                     -> (RefMut<'a, T>, R)
+        where F: FnOnce() -> R
+    {
+        debug_assert!(&*guard as *const T == self.value.as_unsafe_cell().get() as *const T,
+                      "scoped_unborrow called with a guard for another cell");
+        drop(guard);
+        let result = f();
+        (self.borrow_mut(), result)
+    }
+}
+
+impl<T: JSTraceable> JSTraceable for DOMRefCell<T> {
+    #[allow(unsafe_blocks)]
+    fn trace(&self, trc: *mut JSTracer) {
+        // A GC can happen while a `RefMut` is held across an allocation, so
+        // this must not go through `borrow()`.
+        unsafe {
+            (*self).borrow_for_gc_trace().trace(trc)
+        }
+    }
+}
+
+/// Traces the contents of each of `cells`, like calling `trace` on each of
+/// them, but checking the task state only once.
+#[allow(unsafe_blocks)]
+pub fn trace_cells<T: JSTraceable>(cells: &[DOMRefCell<T>], trc: *mut JSTracer) {
+    debug_assert!(task_state::get().contains(SCRIPT | IN_GC));
+    for cell in cells.iter() {
+        // See the `JSTraceable` impl for `DOMRefCell`.
+        unsafe {
+            (*cell.value.as_unsafe_cell().get()).trace(trc)
+        }
+    }
+}
+
+impl<T: JSTraceable> DOMRefCell<T> {
+    /// Replaces the wrapped value with a new one, returning the old value.
+    ///
+    /// When this is called during GC, for instance from a trace hook, pass
+    /// the tracer as `trc`: both the old and the new value are then traced
+    /// with it, so that neither is missed by the collection in progress.
+    /// With `None`, this is just `replace`.
+    ///
+    /// The caller has to say whether a GC is in progress, because the task
+    /// state only tracks it in debug builds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn replace_traced(&self, value: T, trc: Option<*mut JSTracer>) -> T {
+        let old = self.replace(value);
+        if let Some(trc) = trc {
+            old.trace(trc);
+            self.borrow().trace(trc);
+        }
+        old
+    }
+}
+
+/// Immutably borrows both `a` and `b`.
+///
+/// Returns `None`, without holding either borrow, if either value is
+/// currently mutably borrowed.
+///
+/// # Panics
+///
+/// Panics if this is called off the script thread.
+pub fn borrow_both<'a, A, B>(a: &'a DOMRefCell<A>, b: &'a DOMRefCell<B>)
+                             -> Option<(Ref<'a, A>, Ref<'a, B>)> {
+    let a = match a.try_borrow().into_option() {
+        Some(a) => a,
+        None => return None,
+    };
+    match b.try_borrow().into_option() {
+        Some(b) => Some((a, b)),
+        None => None,
+    }
+}
+
+/// Mutably borrows both `a` and `b`.
+///
+/// Returns `None`, without holding either borrow, if either value is
+/// currently borrowed or if `a` and `b` are the same cell.
+///
+/// # Panics
+///
+/// Panics if this is called off the script thread.
+pub fn borrow_both_mut<'a, A, B>(a: &'a DOMRefCell<A>, b: &'a DOMRefCell<B>)
+                                 -> Option<(RefMut<'a, A>, RefMut<'a, B>)> {
+    if a as *const DOMRefCell<A> as *const () == b as *const DOMRefCell<B> as *const () {
+        return None;
+    }
+    let a = match a.try_borrow_mut().into_option() {
+        Some(a) => a,
+        None => return None,
+    };
+    match b.try_borrow_mut().into_option() {
+        Some(b) => Some((a, b)),
+        None => None,
+    }
+}
+
+/// Mutably borrows all of `cells`, returning the guards in the same order.
+///
+/// The cells are borrowed in address order. Returns `None`, without holding
+/// any borrow, if any value is currently borrowed or if a cell appears more
+/// than once.
+///
+/// # Panics
+///
+/// Panics if this is called off the script thread.
+pub fn borrow_all_mut<'a, T>(cells: &'a [&'a DOMRefCell<T>]) -> Option<Vec<RefMut<'a, T>>> {
+    let address = |index: uint| cells[index] as *const DOMRefCell<T> as uint;
+    let mut order: Vec<uint> = range(0, cells.len()).collect();
+    order.sort_by(|&a, &b| address(a).cmp(&address(b)));
+    if order.windows(2).any(|pair| address(pair[0]) == address(pair[1])) {
+        return None;
+    }
+
+    let mut guards: Vec<Option<RefMut<'a, T>>> = range(0, cells.len()).map(|_| None).collect();
+    for &index in order.iter() {
+        match cells[index].try_borrow_mut().into_option() {
+            Some(guard) => guards[index] = Some(guard),
+            None => return None,
+        }
+    }
+    Some(guards.into_iter().map(|guard| guard.unwrap()).collect())
+}
+
+/// A wrapper whose contents are never traced by the GC.
+///
+/// Wrap `DOMRefCell` fields that only hold plain data (strings, numbers) in
+/// this, so tracing skips them instead of borrowing and walking them.
+/// Putting anything that holds JS-managed values in here is a GC hazard.
+pub struct Untraced<T> {
+    value: T,
+}
+
+impl<T> Untraced<T> {
+    /// Create a new `Untraced` wrapping `value`.
+    pub fn new(value: T) -> Untraced<T> {
+        Untraced {
+            value: value,
+        }
+    }
+}
+
+impl<T> Deref for Untraced<T> {
+    type Target = T;
+    fn deref<'a>(&'a self) -> &'a T {
+        &self.value
+    }
+}
+
+impl<T> JSTraceable for Untraced<T> {
+    #[inline]
+    fn trace(&self, _: *mut JSTracer) {
+        // Do nothing
+    }
+}
+
+/// A `DOMRefCell` whose contents are only traced while a predicate holds.
+///
+/// Without a predicate, this traces exactly like a `DOMRefCell`. The
+/// predicate must only return `false` when the value provably holds no
+/// JS-managed values; otherwise skipping the trace is a GC hazard.
+pub struct ConditionallyTraced<T> {
+    cell: DOMRefCell<T>,
+    predicate: Cell<Option<fn(&T) -> bool>>,
+}
+
+impl<T> ConditionallyTraced<T> {
+    /// Create a new `ConditionallyTraced` containing `value`, with no
+    /// predicate.
+    pub fn new(value: T) -> ConditionallyTraced<T> {
+        ConditionallyTraced {
+            cell: DOMRefCell::new(value),
+            predicate: Cell::new(None),
+        }
+    }
+
+    /// Only trace the contents while `predicate` returns `true` for them.
+    ///
+    /// This replaces any previously set predicate.
+    pub fn set_trace_predicate(&self, predicate: fn(&T) -> bool) {
+        self.predicate.set(Some(predicate));
+    }
+
+    /// Remove the predicate, so that the contents are always traced.
+    pub fn clear_trace_predicate(&self) {
+        self.predicate.set(None);
+    }
+}
+
+impl<T> Deref for ConditionallyTraced<T> {
+    type Target = DOMRefCell<T>;
+    fn deref<'a>(&'a self) -> &'a DOMRefCell<T> {
+        &self.cell
+    }
+}
+
+impl<T: JSTraceable> JSTraceable for ConditionallyTraced<T> {
+    #[allow(unsafe_blocks)]
+    fn trace(&self, trc: *mut JSTracer) {
+        // See the `JSTraceable` impl for `DOMRefCell`.
+        unsafe {
+            let value = self.cell.borrow_for_gc_trace();
+            match self.predicate.get() {
+                Some(predicate) if !predicate(value) => {}
+                _ => value.trace(trc),
+            }
+        }
+    }
+}
+
+/// A `DOMRefCell` whose tracing skips the contents, rather than reading
+/// them, while they are mutably borrowed.
+///
+/// This trades a possibly missed edge for never reading a value that is in
+/// the middle of being mutated, so only use it for fields that have been
+/// checked to be safe to skip.
+pub struct GcSafe<T> {
+    cell: DOMRefCell<T>,
+}
+
+impl<T> GcSafe<T> {
+    /// Create a new `GcSafe` containing `value`.
+    pub fn new(value: T) -> GcSafe<T> {
+        GcSafe {
+            cell: DOMRefCell::new(value),
+        }
+    }
+}
+
+impl<T> Deref for GcSafe<T> {
+    type Target = DOMRefCell<T>;
+    fn deref<'a>(&'a self) -> &'a DOMRefCell<T> {
+        &self.cell
+    }
+}
+
+impl<T: JSTraceable> JSTraceable for GcSafe<T> {
+    fn trace(&self, trc: *mut JSTracer) {
+        debug_assert!(task_state::get().contains(SCRIPT | IN_GC));
+        match self.cell.value.try_borrow() {
+            Some(value) => value.trace(trc),
+            None => debug!("skipped tracing a mutably borrowed DOMRefCell"),
+        }
+    }
+}
+
+/// A `DOMRefCell` that becomes unusable once a mutable borrow of it is
+/// released by a panic, like a poisoned `Mutex`.
+///
+/// A panic in the middle of a mutation can leave the value inconsistent;
+/// poisoning stops that from spreading to later borrowers.
+pub struct Poisonable<T> {
+    cell: DOMRefCell<T>,
+    poisoned: Cell<bool>,
+}
+
+/// A mutable borrow of a `Poisonable`, returned by `Poisonable::borrow_mut`.
+///
+/// If this is dropped while the thread is panicking, the cell is poisoned.
+pub struct PoisonGuard<'a, T: 'a> {
+    guard: RefMut<'a, T>,
+    poisoned: &'a Cell<bool>,
+}
+
+impl<T> Poisonable<T> {
+    /// Create a new, unpoisoned `Poisonable` containing `value`.
+    pub fn new(value: T) -> Poisonable<T> {
+        Poisonable {
+            cell: DOMRefCell::new(value),
+            poisoned: Cell::new(false),
+        }
+    }
+
+    /// Was a mutable borrow of this cell released by a panic?
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.get()
+    }
+
+    /// Immutably borrows the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the cell is poisoned or the value is currently mutably
+    /// borrowed.
+    pub fn borrow<'a>(&'a self) -> Ref<'a, T> {
+        if self.is_poisoned() {
+            panic!("DOMRefCell<T> poisoned");
+        }
+        self.cell.borrow()
+    }
+
+    /// Mutably borrows the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the cell is poisoned or the value is currently borrowed.
+    pub fn borrow_mut<'a>(&'a self) -> PoisonGuard<'a, T> {
+        if self.is_poisoned() {
+            panic!("DOMRefCell<T> poisoned");
+        }
+        PoisonGuard {
+            guard: self.cell.borrow_mut(),
+            poisoned: &self.poisoned,
+        }
+    }
+
+    /// Attempts to immutably borrow the wrapped value, like
+    /// `DOMRefCell::try_borrow_checked`, failing with
+    /// `BorrowFailure::Poisoned` if the cell is poisoned.
+    pub fn try_borrow_checked<'a>(&'a self) -> Result<Ref<'a, T>, BorrowError> {
+        if self.is_poisoned() {
+            return Err(BorrowError { reason: BorrowFailure::Poisoned });
+        }
+        self.cell.try_borrow_checked()
+    }
+
+    /// Attempts to mutably borrow the wrapped value, like
+    /// `DOMRefCell::try_borrow_mut_checked`, failing with
+    /// `BorrowFailure::Poisoned` if the cell is poisoned.
+    pub fn try_borrow_mut_checked<'a>(&'a self) -> Result<PoisonGuard<'a, T>, BorrowMutError> {
+        if self.is_poisoned() {
+            return Err(BorrowMutError { reason: BorrowFailure::Poisoned });
+        }
+        self.cell.try_borrow_mut_checked().map(|guard| {
+            PoisonGuard {
+                guard: guard,
+                poisoned: &self.poisoned,
+            }
+        })
+    }
+}
+
+impl<T: JSTraceable> JSTraceable for Poisonable<T> {
+    #[inline]
+    fn trace(&self, trc: *mut JSTracer) {
+        self.cell.trace(trc)
+    }
+}
+
+impl<'a, T> Deref for PoisonGuard<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+        &*self.guard
+    }
+}
+
+impl<'a, T> DerefMut for PoisonGuard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T {
+        &mut *self.guard
+    }
+}
+
+#[unsafe_destructor]
+impl<'a, T> Drop for PoisonGuard<'a, T> {
+    fn drop(&mut self) {
+        if Thread::panicking() {
+            self.poisoned.set(true);
+        }
+    }
+}
+
+/// A `DOMRefCell` that calls a callback whenever a mutable borrow of it is
+/// released, for dirty tracking.
+///
+/// The callback runs after the borrow is released, so it may borrow the
+/// cell itself, but it must not call `on_mutate` or `clear_on_mutate`.
+/// Tracing never takes a mutable borrow, so it never fires the callback.
+pub struct Watched<T> {
+    cell: DOMRefCell<T>,
+    on_mutate: RefCell<Option<Box<Fn() + 'static>>>,
+}
+
+/// A mutable borrow of a `Watched`, returned by `Watched::borrow_mut`.
+pub struct WatchGuard<'a, T: 'a> {
+    guard: Option<RefMut<'a, T>>,
+    watched: &'a Watched<T>,
+}
+
+impl<T> Watched<T> {
+    /// Create a new `Watched` containing `value`, with no callback.
+    pub fn new(value: T) -> Watched<T> {
+        Watched {
+            cell: DOMRefCell::new(value),
+            on_mutate: RefCell::new(None),
+        }
+    }
+
+    /// Call `callback` whenever a mutable borrow of the cell is released,
+    /// replacing any previously registered callback.
+    pub fn on_mutate(&self, callback: Box<Fn() + 'static>) {
+        *self.on_mutate.borrow_mut() = Some(callback);
+    }
+
+    /// Unregister the callback registered with `on_mutate`, if any.
+    pub fn clear_on_mutate(&self) {
+        *self.on_mutate.borrow_mut() = None;
+    }
+
+    /// Immutably borrows the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow<'a>(&'a self) -> Ref<'a, T> {
+        self.cell.borrow()
+    }
+
+    /// Mutably borrows the wrapped value, calling the `on_mutate` callback
+    /// when the borrow is released.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut<'a>(&'a self) -> WatchGuard<'a, T> {
+        WatchGuard {
+            guard: Some(self.cell.borrow_mut()),
+            watched: self,
+        }
+    }
+}
+
+impl<T: JSTraceable> JSTraceable for Watched<T> {
+    #[inline]
+    fn trace(&self, trc: *mut JSTracer) {
+        self.cell.trace(trc)
+    }
+}
+
+impl<'a, T> Deref for WatchGuard<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+        &**self.guard.as_ref().unwrap()
+    }
+}
+
+impl<'a, T> DerefMut for WatchGuard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T {
+        &mut **self.guard.as_mut().unwrap()
+    }
+}
+
+#[unsafe_destructor]
+impl<'a, T> Drop for WatchGuard<'a, T> {
+    fn drop(&mut self) {
+        // Release the borrow before calling back.
+        self.guard.take();
+        if task_state::get().contains(IN_GC) {
+            return;
+        }
+        if let Some(ref callback) = *self.watched.on_mutate.borrow() {
+            (**callback)();
+        }
+    }
+}
+
+// Functionality duplicated with `core::cell::RefCell`
+// ===================================================
+impl<T> DOMRefCell<T> {
+    /// Create a new `DOMRefCell` containing `value`.
+    pub fn new(value: T) -> DOMRefCell<T> {
+        DOMRefCell {
+            value: RefCell::new(value),
+            diagnostics: CellDiagnostics::new(),
+            _nosend: marker::NoSend,
+        }
+    }
+
+    /// Create a new `DOMRefCell` containing `value` whose tracing skips the
+    /// contents while they are mutably borrowed. See `GcSafe`.
+    pub fn new_gc_safe(value: T) -> GcSafe<T> {
+        GcSafe::new(value)
+    }
+
+    /// Create a new `DOMRefCell` containing `value` that is poisoned if a
+    /// mutable borrow of it is released by a panic. See `Poisonable`.
+    pub fn new_poisonable(value: T) -> Poisonable<T> {
+        Poisonable::new(value)
+    }
+
+    /// Create a new `DOMRefCell` containing `value` that calls a callback
+    /// whenever a mutable borrow of it is released. See `Watched`.
+    pub fn new_watched(value: T) -> Watched<T> {
+        Watched::new(value)
+    }
+
+    /// Create a new `DOMRefCell` containing `value`, after calling `init` on
+    /// it.
+    ///
+    /// `init` gets a plain `&mut` to the value, since nothing else can see it
+    /// yet, so no borrow is taken.
+    pub fn build<F: FnOnce(&mut T)>(init: F, mut value: T) -> DOMRefCell<T> {
+        init(&mut value);
+        DOMRefCell::new(value)
+    }
+
+    /// Create a new `DOMRefCell` containing `value`, when rebuilding the DOM
+    /// from a serialized snapshot.
+    ///
+    /// This is the same as `new`; it exists to mark snapshot loading code
+    /// apart from ordinary field initialization.
+    #[inline]
+    pub fn from_value_assuming_fresh(value: T) -> DOMRefCell<T> {
+        DOMRefCell::new(value)
+    }
+
+    /// Create a new `DOMRefCell` containing `value` that the GC will never
+    /// trace. See `Untraced`.
+    pub fn new_untraced(value: T) -> Untraced<DOMRefCell<T>> {
+        Untraced::new(DOMRefCell::new(value))
+    }
+
+    /// Create a new `DOMRefCell` from an existing `RefCell`, without moving
+    /// its contents.
+    ///
+    /// In debug builds, asserts that `value` is not borrowed; it can only be
+    /// if a guard was leaked.
+    pub fn from_refcell(value: RefCell<T>) -> DOMRefCell<T> {
+        debug_assert!(value.try_borrow_mut().is_some());
+        DOMRefCell {
+            value: value,
+            diagnostics: CellDiagnostics::new(),
+            _nosend: marker::NoSend,
+        }
+    }
+
+
+    /// Immutably borrows the wrapped value.
+    ///
+    /// The borrow lasts until the returned `Ref` exits scope. Multiple
+    /// immutable borrows can be taken out at the same time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow<'a>(&'a self) -> Ref<'a, T> {
+        match self.try_borrow().into_option() {
+            Some(ptr) => ptr,
+            None => {
+                self.borrow_panicked(BorrowEventKind::Shared, None);
+                panic!("DOMRefCell<T> already mutably borrowed")
+            }
+        }
+    }
+
+    /// Mutably borrows the wrapped value.
+    ///
+    /// The borrow lasts until the returned `RefMut` exits scope. The value
+    /// cannot be borrowed while this borrow is active.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut<'a>(&'a self) -> RefMut<'a, T> {
+        match self.try_borrow_mut().into_option() {
+            Some(ptr) => ptr,
+            None => {
+                self.borrow_panicked(BorrowEventKind::Mutable, None);
+                if self.diagnostics.frozen() {
+                    panic!("DOMRefCell<T> frozen")
+                }
+                panic!("DOMRefCell<T> already borrowed")
+            }
+        }
+    }
+
+    /// Immutably borrows the wrapped value, like `borrow`, returning the
+    /// guard as a boxed trait object.
+    ///
+    /// This is for accessors that only sometimes read from a cell, so they
+    /// can return the same type either way.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow_boxed<'a>(&'a self) -> Box<Deref<Target=T> + 'a> {
+        box self.borrow()
+    }
+
+    /// Mutably borrows the wrapped value, like `borrow_mut`, and sets `dirty`
+    /// once the borrow is taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed, leaving `dirty` unchanged.
+    pub fn borrow_mut_dirtying<'a>(&'a self, dirty: &'a Cell<bool>) -> RefMut<'a, T> {
+        let borrow = self.borrow_mut();
+        dirty.set(true);
+        borrow
+    }
+
+    /// Mutably borrows the wrapped value, like `borrow_mut`, returning the
+    /// borrow as a `Guard`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut_wrapped<'a>(&'a self) -> Guard<'a, T> {
+        Guard(self.borrow_mut())
+    }
+
+    /// Mutably borrows the wrapped value, like `borrow_mut`, for a field
+    /// that should never be contended.
+    ///
+    /// `invariant` states why the borrow is expected to succeed, and is
+    /// included in the panic message if it does not.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut_expecting<'a>(&'a self, invariant: &'static str) -> RefMut<'a, T> {
+        match self.try_borrow_mut().into_option() {
+            Some(ptr) => ptr,
+            None => {
+                self.borrow_panicked(BorrowEventKind::Mutable, None);
+                panic!("invariant violated: {} (DOMRefCell<T> already borrowed)", invariant)
+            }
+        }
+    }
+
+    /// Immutably borrows the wrapped value and projects the borrow onto the
+    /// part of it returned by `f`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow_map<'a, U: ?Sized, F>(&'a self, f: F) -> MappedRef<'a, T, U>
+        where F: FnOnce(&T) -> &U
+    {
+        let guard = self.borrow();
+        let value = f(&*guard) as *const U;
+        MappedRef {
+            _guard: guard,
+            value: value,
+        }
+    }
+
+    /// Mutably borrows the wrapped value and projects the borrow onto the
+    /// part of it returned by `f`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut_map<'a, U: ?Sized, F>(&'a self, f: F) -> MappedRefMut<'a, T, U>
+        where F: FnOnce(&mut T) -> &mut U
+    {
+        let mut guard = self.borrow_mut();
+        let value = f(&mut *guard) as *mut U;
+        MappedRefMut {
+            _guard: guard,
+            value: value,
+        }
+    }
+
+    /// Immutably borrows the wrapped value and projects the borrow onto the
+    /// part of it returned by `f`, if any.
+    ///
+    /// If `f` returns `None`, the borrow is released and `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow_filter_map<'a, U, F>(&'a self, f: F) -> Option<MappedRef<'a, T, U>>
+        where F: FnOnce(&T) -> Option<&U>
+    {
+        let guard = self.borrow();
+        let value = match f(&*guard) {
+            Some(value) => value as *const U,
+            None => return None,
+        };
+        Some(MappedRef {
+            _guard: guard,
+            value: value,
+        })
+    }
+
+    /// Mutably borrows the wrapped value and splits the borrow into two
+    /// guards, for the two disjoint parts of it returned by `f`.
+    ///
+    /// The halves share the borrow through an `Rc`, so this allocates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut_split<'a, U: ?Sized, V: ?Sized, F>(&'a self, f: F)
+                                                       -> (SplitRefMut<'a, T, U>,
+                                                           SplitRefMut<'a, T, V>)
+        where F: FnOnce(&mut T) -> (&mut U, &mut V)
+    {
+        let mut guard = self.borrow_mut();
+        let (u, v) = {
+            let (u, v) = f(&mut *guard);
+            (u as *mut U, v as *mut V)
+        };
+        let guard = Rc::new(guard);
+        (SplitRefMut { _guard: guard.clone(), value: u },
+         SplitRefMut { _guard: guard, value: v })
+    }
+
+    /// Mutably borrows the wrapped value and projects the borrow onto the
+    /// part of it returned by `f`, if any.
+    ///
+    /// If `f` returns `None`, the borrow is released and `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut_filter_map<'a, U, F>(&'a self, f: F) -> Option<MappedRefMut<'a, T, U>>
+        where F: FnOnce(&mut T) -> Option<&mut U>
+    {
+        let mut guard = self.borrow_mut();
+        let value = match f(&mut *guard) {
+            Some(value) => value as *mut U,
+            None => return None,
+        };
+        Some(MappedRefMut {
+            _guard: guard,
+            value: value,
+        })
+    }
+
+    /// Immutably borrows the wrapped value and projects the borrow onto the
+    /// part of it returned by `f`, or returns the error `f` returned.
+    ///
+    /// If `f` returns an error, the borrow is released before returning it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn borrow_try_map<'a, U: ?Sized, E, F>(&'a self, f: F) -> Result<MappedRef<'a, T, U>, E>
+        where F: FnOnce(&T) -> Result<&U, E>
+    {
+        let guard = self.borrow();
+        let value = try!(f(&*guard)) as *const U;
+        Ok(MappedRef {
+            _guard: guard,
+            value: value,
+        })
+    }
+
+    /// Mutably borrows the wrapped value without any dynamic checks.
+    ///
+    /// Since this requires `&mut self`, the borrow is checked statically and
+    /// no `Ref` or `RefMut` can be alive. For the same reason, no task state
+    /// assertion is performed: having exclusive access to the cell is enough.
+    #[allow(unsafe_blocks)]
+    pub fn get_mut<'a>(&'a mut self) -> &'a mut T {
+        unsafe {
+            &mut *self.value.as_unsafe_cell().get()
+        }
+    }
+
+    /// Consumes the `DOMRefCell`, returning the wrapped value.
+    ///
+    /// Taking `self` by value guarantees that no borrows are outstanding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    #[allow(unsafe_blocks)]
+    pub fn into_inner(self) -> T {
+        debug_assert!(task_state::get().is_script());
+        // `DOMRefCell` implements `Drop` in debug builds, so `value` can't
+        // simply be moved out.
+        unsafe {
+            let value = ptr::read(&self.value);
+            mem::forget(self);
+            value.into_inner()
+        }
+    }
+
+    /// Consumes the `DOMRefCell`, returning the wrapped value if it is not
+    /// borrowed.
+    ///
+    /// A borrow can only be outstanding here if a guard was leaked, or one
+    /// of the unsafe methods above was misused. In that case, this returns
+    /// the cell unchanged in `Err` instead of panicking.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn try_into_inner(self) -> Result<T, DOMRefCell<T>> {
+        let unborrowed = self.value.try_borrow_mut().is_some();
+        if unborrowed {
+            Ok(self.into_inner())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Consumes the `DOMRefCell`, returning a new one containing the result
+    /// of calling `f` on the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn map_into<U, F: FnOnce(T) -> U>(self, f: F) -> DOMRefCell<U> {
+        DOMRefCell::new(f(self.into_inner()))
+    }
+
+    /// Consumes the `DOMRefCell`, returning the underlying `RefCell`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    #[allow(unsafe_blocks)]
+    pub fn into_refcell(self) -> RefCell<T> {
+        debug_assert!(task_state::get().is_script());
+        // See `into_inner`.
+        unsafe {
+            let value = ptr::read(&self.value);
+            mem::forget(self);
+            value
+        }
+    }
+
+    /// Replaces the wrapped value with a new one, returning the old value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn replace(&self, value: T) -> T {
+        mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    /// Replaces the wrapped value with a new one computed from `f`, returning
+    /// the old value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn replace_with<F: FnOnce(&mut T) -> T>(&self, f: F) -> T {
+        let mut value = self.borrow_mut();
+        let new = f(&mut *value);
+        mem::replace(&mut *value, new)
+    }
+
+    /// Takes the wrapped value, leaving the result of `make_empty` in its
+    /// place.
+    ///
+    /// This is `take` for types without a cheap `Default`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn take_with<F: FnOnce() -> T>(&self, make_empty: F) -> T {
+        let mut value = self.borrow_mut();
+        mem::replace(&mut *value, make_empty())
+    }
+
+    /// Replaces the wrapped value with `new` if `predicate` returns `true`
+    /// for it, returning the old value.
+    ///
+    /// Returns `None`, dropping `new`, if `predicate` returns `false`. The
+    /// value stays mutably borrowed from the check to the replacement.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn replace_if<P: FnOnce(&T) -> bool>(&self, predicate: P, new: T) -> Option<T> {
+        let mut value = self.borrow_mut();
+        if predicate(&*value) {
+            Some(mem::replace(&mut *value, new))
+        } else {
+            None
+        }
+    }
+
+    /// Attempts to replace the wrapped value with a new one computed from
+    /// `f`, returning the old value.
+    ///
+    /// Returns `None` without calling `f` if the value is currently borrowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    pub fn try_replace_with<F: FnOnce(&mut T) -> T>(&self, f: F) -> Option<T> {
+        self.try_borrow_mut().map(|mut value| {
+            let new = f(&mut *value);
+            mem::replace(&mut *value, new)
+        })
+    }
+
+    /// Swaps the wrapped value of `self` with the wrapped value of `other`.
+    ///
+    /// Swapping an unborrowed cell with itself is a no-op.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value in either cell is currently borrowed.
+    pub fn swap(&self, other: &DOMRefCell<T>) {
+        let mut this = match self.try_borrow_mut().into_option() {
+            Some(ptr) => ptr,
+            None => {
+                self.borrow_panicked(BorrowEventKind::Mutable, None);
+                panic!("DOMRefCell<T> already borrowed (swap: self)")
+            }
+        };
+        if DOMRefCell::ptr_eq(self, other) {
+            return;
+        }
+        let mut other = match other.try_borrow_mut().into_option() {
+            Some(ptr) => ptr,
+            None => {
+                other.borrow_panicked(BorrowEventKind::Mutable, None);
+                panic!("DOMRefCell<T> already borrowed (swap: other)")
+            }
+        };
+        mem::swap(&mut *this, &mut *other);
+    }
+
+    /// Swaps the wrapped value with `other`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn swap_inner(&self, other: &mut T) {
+        mem::swap(&mut *self.borrow_mut(), other);
+    }
+}
+
+/// Checks that no borrow of the cell is outstanding when it is dropped.
+///
+/// A leaked borrow can only come from misusing one of the unsafe methods
+/// above, so this only exists in debug builds.
+#[cfg(not(ndebug))]
+#[unsafe_destructor]
+impl<T> Drop for DOMRefCell<T> {
+    fn drop(&mut self) {
+        debug_assert!(self.value.try_borrow_mut().is_some(),
+                      "DOMRefCell<T> dropped while borrowed");
+    }
+}
+
+impl<T: Clone> Clone for DOMRefCell<T> {
+    /// Returns a new `DOMRefCell` containing a clone of the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    fn clone(&self) -> DOMRefCell<T> {
+        DOMRefCell::new(self.borrow().clone())
+    }
+}
+
+impl<T: PartialEq> PartialEq for DOMRefCell<T> {
+    /// Compares the wrapped values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value in either cell is currently mutably borrowed.
+    fn eq(&self, other: &DOMRefCell<T>) -> bool {
+        *self.borrow() == *other.borrow()
+    }
+}
+
+impl<T: Eq> Eq for DOMRefCell<T> {}
+
+impl<H: Hasher, T: Hash<H>> Hash<H> for DOMRefCell<T> {
+    /// Hashes the wrapped value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    fn hash(&self, state: &mut H) {
+        self.borrow().hash(state)
+    }
+}
+
+impl<T: Default> Default for DOMRefCell<T> {
+    fn default() -> DOMRefCell<T> {
+        DOMRefCell::new(Default::default())
+    }
+}
+
+impl<T: fmt::Show> fmt::Show for DOMRefCell<T> {
+    /// Formats the wrapped value.
+    ///
+    /// This never panics: a placeholder is printed instead if the value is
+    /// mutably borrowed or if this is called off the script thread.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if cfg!(not(ndebug)) && !task_state::try_get().map_or(false, |state| state.is_script()) {
+            return write!(f, "DOMRefCell {{ value: <off script thread> }}");
+        }
+        match self.value.try_borrow() {
+            Some(value) => write!(f, "DOMRefCell {{ value: {:?} }}", *value),
+            None => write!(f, "DOMRefCell {{ value: <borrowed> }}"),
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<T: Encodable> Encodable for DOMRefCell<T> {
+    /// Encodes the wrapped value as if it were not in a cell.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed: `Encoder` provides
+    /// no way to report a custom error.
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        match self.try_borrow().into_option() {
+            Some(value) => value.encode(s),
+            None => panic!("DOMRefCell<T> already mutably borrowed while encoding"),
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<T: Decodable> Decodable for DOMRefCell<T> {
+    /// Decodes a value and wraps it in a new `DOMRefCell`.
+    fn decode<D: Decoder>(d: &mut D) -> Result<DOMRefCell<T>, D::Error> {
+        Decodable::decode(d).map(DOMRefCell::new)
+    }
+}
+
+impl<T: Default> DOMRefCell<T> {
+    /// Takes the wrapped value, leaving `Default::default()` in its place.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn take(&self) -> T {
+        self.replace(Default::default())
+    }
+}
+
+/// Scoped access to a value in a DOM object field, however it is stored.
+///
+/// Algorithms written against this trait rather than against `DOMRefCell`
+/// keep working if the field is changed to a representation that needs no
+/// dynamic borrow checks.
+pub trait DomBorrow<T> {
+    /// Immutably borrows the value for the duration of `f`.
+    fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R;
+
+    /// Mutably borrows the value for the duration of `f`.
+    fn with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R;
+}
+
+impl<T> DomBorrow<T> for DOMRefCell<T> {
+    /// See `DOMRefCell::with`.
+    fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        DOMRefCell::with(self, f)
+    }
+
+    /// See `DOMRefCell::with_mut`.
+    fn with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        DOMRefCell::with_mut(self, f)
+    }
+}
+
+/// Slice access to a `DOMRefCell<Vec<T>>`.
+pub trait DOMRefCellVecHelpers<T> {
+    /// Immutably borrows the wrapped vector as a slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    fn borrow_slice<'a>(&'a self) -> MappedRef<'a, Vec<T>, [T]>;
+
+    /// Mutably borrows the wrapped vector as a slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    fn borrow_slice_mut<'a>(&'a self) -> MappedRefMut<'a, Vec<T>, [T]>;
+
+    /// Moves all the elements of the wrapped vector to the end of `dest`,
+    /// leaving the wrapped vector empty but keeping its capacity.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    fn drain_into(&self, dest: &mut Vec<T>);
+
+    /// Removes all the elements of the wrapped vector, keeping its capacity.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    fn clear(&self);
+}
+
+impl<T> DOMRefCellVecHelpers<T> for DOMRefCell<Vec<T>> {
+    fn borrow_slice<'a>(&'a self) -> MappedRef<'a, Vec<T>, [T]> {
+        self.borrow_map(|vec| vec.as_slice())
+    }
+
+    fn borrow_slice_mut<'a>(&'a self) -> MappedRefMut<'a, Vec<T>, [T]> {
+        self.borrow_mut_map(|vec| vec.as_mut_slice())
+    }
+
+    fn drain_into(&self, dest: &mut Vec<T>) {
+        dest.extend(self.borrow_mut().drain());
+    }
+
+    fn clear(&self) {
+        self.borrow_mut().clear()
+    }
+}
+
+impl<T> DOMRefCell<Option<T>> {
+    /// Mutably borrows the wrapped value, first setting it to the result of
+    /// `init` if it is `None`, and projects the borrow onto the contents.
+    ///
+    /// `init` is only called if the value is `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_mut_or_init<'a, F>(&'a self, init: F) -> MappedRefMut<'a, Option<T>, T>
+        where F: FnOnce() -> T
+    {
+        self.borrow_mut_map(|value| {
+            if value.is_none() {
+                *value = Some(init());
+            }
+            value.as_mut().unwrap()
+        })
+    }
+}
+
+impl<T: 'static> DOMRefCell<T> {
+    /// Immutably borrows the wrapped value, recording how long the borrow
+    /// is held for in the total for `T` on this thread.
+    ///
+    /// This is for profiling which fields are borrowed for longest. In
+    /// release builds, nothing is recorded and this is just `borrow`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    #[cfg(not(ndebug))]
+    pub fn borrow_timed<'a>(&'a self) -> TimedRef<'a, T> {
+        let guard = self.borrow();
+        TimedRef {
+            guard: guard,
+            borrowed_at: time::precise_time_ns(),
+        }
+    }
+
+    #[cfg(ndebug)]
+    #[inline(always)]
+    pub fn borrow_timed<'a>(&'a self) -> TimedRef<'a, T> {
+        TimedRef {
+            guard: self.borrow(),
+        }
+    }
+
+    /// Returns the total time borrows of a `T` taken with `borrow_timed` on
+    /// this thread have been held for.
+    ///
+    /// Always returns zero in release builds.
+    #[cfg(not(ndebug))]
+    pub fn total_borrow_time() -> Duration {
+        let total = BORROW_TIMES.with(|times| {
+            times.borrow().get(&TypeId::of::<T>()).map_or(0, |total| *total)
+        });
+        Duration::nanoseconds(total as i64)
+    }
+
+    #[cfg(ndebug)]
+    #[inline(always)]
+    pub fn total_borrow_time() -> Duration {
+        Duration::zero()
+    }
+}
+
+impl<A, B> DOMRefCell<(A, B)> {
+    /// Mutably borrows the wrapped pair once, and calls `f` with a mutable
+    /// reference to each half.
+    ///
+    /// To do the same for the fields of a struct, use `borrow_split_mut!`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn borrow_split_mut<R, F: FnOnce(&mut A, &mut B) -> R>(&self, f: F) -> R {
+        let mut value = self.borrow_mut();
+        let value = &mut *value;
+        f(&mut value.0, &mut value.1)
+    }
+}
+
+impl<T: Copy> DOMRefCell<T> {
+    /// Returns a copy of the wrapped value.
+    ///
+    /// This is the preferred way to read `Copy` fields. The borrow it takes
+    /// is released before this returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed.
+    pub fn get(&self) -> T {
+        *self.borrow()
+    }
+
+    /// Sets the wrapped value.
+    ///
+    /// This is the preferred way to write `Copy` fields. The borrow it takes
+    /// is released before this returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn set(&self, value: T) {
+        *self.borrow_mut() = value;
+    }
+
+    /// Replaces the wrapped value with the result of calling `f` on it, and
+    /// returns the new value.
+    ///
+    /// This reads and writes the value with `get` and `set`, so no borrow is
+    /// held while `f` runs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently borrowed.
+    pub fn update<F: FnOnce(T) -> T>(&self, f: F) -> T {
+        let value = f(self.get());
+        self.set(value);
+        value
+    }
+}
+
+impl<T: Copy + PartialEq> DOMRefCell<T> {
+    /// Sets the wrapped value to `new` if it is currently equal to `current`,
+    /// and returns whether it did.
+    ///
+    /// This reads and writes the value with `get` and `set`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if the value is currently mutably borrowed, or if it is
+    /// currently borrowed and equal to `current`.
+    pub fn compare_and_set(&self, current: T, new: T) -> bool {
+        if self.get() != current {
+            return false;
+        }
+        self.set(new);
+        true
+    }
+}
+
+/// A mutable field in the DOM that may also be read from other threads.
+///
+/// Unlike `DOMRefCell`, this is backed by a real `RwLock`, so readers on the
+/// layout thread do not have to rely on `borrow_for_layout` and debug
+/// assertions for soundness. Only use this for the few fields that need it;
+/// locking is much more expensive than a `RefCell` borrow.
+///
+/// The lock is kept next to the value rather than around it, so that GC
+/// tracing can read the value without taking the lock, like
+/// `DOMRefCell::borrow_for_gc_trace`.
+#[cfg(feature = "rwcell")]
+pub struct DOMRwCell<T> {
+    lock: RwLock<()>,
+    value: UnsafeCell<T>,
+}
+
+// The value is only accessed under `lock`, except by GC tracing on the
+// script thread, which cannot race with the script thread's own writes.
+#[cfg(feature = "rwcell")]
+unsafe impl<T: Send + Sync> Sync for DOMRwCell<T> {}
+
+/// A shared read lock on a `DOMRwCell`, returned by `DOMRwCell::borrow`.
+#[cfg(feature = "rwcell")]
+pub struct DOMRwReadGuard<'a, T: 'a> {
+    _lock: RwLockReadGuard<'a, ()>,
+    value: &'a T,
+}
+
+/// An exclusive write lock on a `DOMRwCell`, returned by
+/// `DOMRwCell::borrow_mut`.
+#[cfg(feature = "rwcell")]
+pub struct DOMRwWriteGuard<'a, T: 'a> {
+    _lock: RwLockWriteGuard<'a, ()>,
+    value: &'a mut T,
+}
+
+#[cfg(feature = "rwcell")]
+impl<T: Send + Sync> DOMRwCell<T> {
+    /// Create a new `DOMRwCell` containing `value`.
+    pub fn new(value: T) -> DOMRwCell<T> {
+        DOMRwCell {
+            lock: RwLock::new(()),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Locks the wrapped value for shared read access, blocking until no
+    /// writer holds the lock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a writer panicked while holding the lock.
+    #[allow(unsafe_blocks)]
+    pub fn borrow<'a>(&'a self) -> DOMRwReadGuard<'a, T> {
+        let lock = self.lock.read().unwrap();
+        DOMRwReadGuard {
+            _lock: lock,
+            // The read lock excludes writers until the guard is dropped.
+            value: unsafe { &*self.value.get() },
+        }
+    }
+
+    /// Locks the wrapped value for exclusive write access, blocking until no
+    /// other reader or writer holds the lock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called off the script thread.
+    ///
+    /// Panics if a writer panicked while holding the lock.
+    #[allow(unsafe_blocks)]
+    pub fn borrow_mut<'a>(&'a self) -> DOMRwWriteGuard<'a, T> {
+        debug_assert!(task_state::get().is_script());
+        let lock = self.lock.write().unwrap();
+        DOMRwWriteGuard {
+            _lock: lock,
+            // The write lock excludes everyone else until the guard is
+            // dropped.
+            value: unsafe { &mut *self.value.get() },
+        }
+    }
+
+    /// Borrow the contents for the purpose of GC tracing, without taking
+    /// the lock.
+    ///
+    /// This succeeds even if the script thread holds a write lock, so you
+    /// have to be careful in trace code!
+    pub unsafe fn borrow_for_gc_trace<'a>(&'a self) -> &'a T {
+        debug_assert!(task_state::get().contains(SCRIPT | IN_GC));
+        &*self.value.get()
+    }
+}
+
+#[cfg(feature = "rwcell")]
+impl<'a, T> Deref for DOMRwReadGuard<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+        self.value
+    }
+}
+
+#[cfg(feature = "rwcell")]
+impl<'a, T> Deref for DOMRwWriteGuard<'a, T> {
+    type Target = T;
+    fn deref<'b>(&'b self) -> &'b T {
+        &*self.value
+    }
+}
+
+#[cfg(feature = "rwcell")]
+impl<'a, T> DerefMut for DOMRwWriteGuard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T {
+        &mut *self.value
+    }
+}
+
+#[cfg(feature = "rwcell")]
+impl<T: JSTraceable + Send + Sync> JSTraceable for DOMRwCell<T> {
+    /// Traces the wrapped value without taking the lock, so a GC is safe
+    /// even while the script thread holds a write lock on this cell.
+    #[allow(unsafe_blocks)]
+    fn trace(&self, trc: *mut JSTracer) {
+        unsafe {
+            (*self.borrow_for_gc_trace()).trace(trc)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BorrowEventKind, BorrowFailure, BorrowPanicInfo, BorrowState, DOMRefCell};
+    use super::{DOMRefCellVecHelpers, DomBorrow, Poisonable};
+    use super::set_borrow_panic_hook;
+    use dom::bindings::trace::JSTraceable;
+    use js::jsapi::JSTracer;
+    use util::task_state;
+    use util::task_state::{SCRIPT, IN_GC};
+
+    use std::borrow::ToOwned;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::mem;
+    use std::ptr;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+    use std::sync::mpsc::{channel, Sender};
+    use std::thread::Builder;
+
+    struct TraceCounter {
+        traced: Rc<Cell<u32>>,
+    }
+
+    impl JSTraceable for TraceCounter {
+        fn trace(&self, _: *mut JSTracer) {
+            self.traced.set(self.traced.get() + 1);
+        }
+    }
+
+    #[test]
+    fn replace() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert_eq!(cell.replace(2), 1);
+        assert_eq!(cell.replace_with(|v| *v + 1), 2);
+        assert_eq!(*cell.borrow(), 3);
+    }
+
+    #[test]
+    #[should_fail]
+    fn replace_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let _borrow = cell.borrow();
+        cell.replace(2);
+    }
+
+    #[test]
+    #[should_fail]
+    fn replace_with_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let _borrow = cell.borrow();
+        cell.replace_with(|v| *v + 1);
+    }
+
+    #[test]
+    fn try_borrow_checked() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        {
+            let _borrow = cell.borrow();
+            assert!(cell.try_borrow_checked().is_ok());
+            assert_eq!(cell.try_borrow_mut_checked().err().unwrap().reason,
+                       BorrowFailure::AlreadyBorrowed);
+        }
+        let _borrow = cell.borrow_mut();
+        assert_eq!(cell.try_borrow_checked().err().unwrap().reason,
+                   BorrowFailure::AlreadyMutablyBorrowed);
+    }
+
+    #[test]
+    fn ptr_eq() {
+        let a = DOMRefCell::new(1u32);
+        let b = DOMRefCell::new(1u32);
+        assert!(DOMRefCell::ptr_eq(&a, &a));
+        assert!(!DOMRefCell::ptr_eq(&a, &b));
+    }
+
+    #[test]
+    fn default() {
+        task_state::initialize(SCRIPT);
+        assert!(DOMRefCell::<Vec<u8>>::default().borrow().is_empty());
+    }
+
+    #[test]
+    fn is_mutably_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert!(!cell.is_mutably_borrowed());
+        {
+            let _borrow = cell.borrow();
+            assert!(!cell.is_mutably_borrowed());
+        }
+        let _borrow = cell.borrow_mut();
+        assert!(cell.is_mutably_borrowed());
+    }
+
+    #[test]
+    fn is_immutably_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert!(!cell.is_immutably_borrowed());
+        {
+            let _borrow = cell.borrow();
+            assert!(cell.is_immutably_borrowed());
+        }
+        let _borrow = cell.borrow_mut();
+        assert!(!cell.is_immutably_borrowed());
+    }
+
+    #[test]
+    fn swap() {
+        task_state::initialize(SCRIPT);
+        let a = DOMRefCell::new(vec![1u32, 2]);
+        let b = DOMRefCell::new(vec![3u32]);
+        a.swap(&b);
+        assert_eq!(*a.borrow(), vec![3u32]);
+        assert_eq!(*b.borrow(), vec![1u32, 2]);
+    }
+
+    #[test]
+    fn swap_with_self() {
+        task_state::initialize(SCRIPT);
+        let a = DOMRefCell::new(vec![1u32, 2]);
+        a.swap(&a);
+        assert_eq!(*a.borrow(), vec![1u32, 2]);
+    }
+
+    #[test]
+    #[should_fail]
+    fn swap_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let a = DOMRefCell::new(vec![1u32, 2]);
+        let b = DOMRefCell::new(vec![3u32]);
+        let _borrow = b.borrow();
+        a.swap(&b);
+    }
+
+    #[test]
+    #[should_fail]
+    fn swap_with_self_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let a = DOMRefCell::new(vec![1u32, 2]);
+        let _borrow = a.borrow_mut();
+        a.swap(&a);
+    }
+
+    #[test]
+    fn clone() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec!["a".to_owned()]);
+        let copy = cell.clone();
+        copy.borrow_mut().push("b".to_owned());
+        assert_eq!(*cell.borrow(), vec!["a".to_owned()]);
+        assert_eq!(*copy.borrow(), vec!["a".to_owned(), "b".to_owned()]);
+    }
+
+    #[test]
+    fn eq() {
+        task_state::initialize(SCRIPT);
+        let a = DOMRefCell::new(1u32);
+        assert!(a == DOMRefCell::new(1u32));
+        assert!(a != DOMRefCell::new(2u32));
+        assert!(a == a);
+    }
+
+    #[test]
+    fn borrow_both() {
+        task_state::initialize(SCRIPT);
+        let a = DOMRefCell::new(1u32);
+        let b = DOMRefCell::new(2u32);
+        assert!(super::borrow_both(&a, &b).is_some());
+        assert!(super::borrow_both_mut(&a, &a).is_none());
+        {
+            let _borrow = b.borrow();
+            assert!(super::borrow_both_mut(&a, &b).is_none());
+        }
+        assert!(!a.is_mutably_borrowed());
+        assert!(super::borrow_both_mut(&a, &b).is_some());
+    }
+
+    #[test]
+    fn swap_inner() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![1u32, 2]);
+        let mut local = vec![3u32];
+        cell.swap_inner(&mut local);
+        assert_eq!(*cell.borrow(), vec![3u32]);
+        assert_eq!(local, vec![1u32, 2]);
+    }
+
+    #[test]
+    fn borrow_state() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        {
+            let _borrow = cell.borrow();
+            assert_eq!(cell.borrow_state(), BorrowState::Shared);
+        }
+        let _borrow = cell.borrow_mut();
+        assert_eq!(cell.borrow_state(), BorrowState::Mutable);
+    }
+
+    #[test]
+    #[cfg(not(ndebug))]
+    fn try_borrow_mut_attempts() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        {
+            let _borrow = cell.borrow();
+            assert!(cell.try_borrow_mut_attempts(1).is_none());
+        }
+        assert!(cell.try_borrow_mut_attempts(1).is_none());
+        cell.reset_blocked_attempts();
+        assert!(cell.try_borrow_mut_attempts(1).is_some());
+    }
+
+    #[test]
+    fn borrow_filter_map() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(Some(1u32));
+        assert_eq!(*cell.borrow_filter_map(|v| v.as_ref()).unwrap(), 1);
+        assert!(cell.borrow_filter_map(|_| None::<&u32>).is_none());
+        assert!(cell.borrow_mut_filter_map(|_| None::<&mut u32>).is_none());
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+    }
+
+    #[test]
+    fn scoped_unborrow() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let guard = cell.borrow_mut();
+        let (mut guard, seen) = cell.scoped_unborrow(guard, || {
+            *cell.borrow_mut() += 1;
+            cell.borrow_state()
+        });
+        assert_eq!(seen, BorrowState::Unborrowed);
+        *guard += 1;
+        assert_eq!(*guard, 3);
+        assert_eq!(cell.borrow_state(), BorrowState::Mutable);
+    }
+
+    #[test]
+    fn hash() {
+        task_state::initialize(SCRIPT);
+        let mut map = HashMap::new();
+        map.insert(DOMRefCell::new("foo".to_owned()), 1u32);
+        map.insert(DOMRefCell::new("foo".to_owned()), 2u32);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&DOMRefCell::new("foo".to_owned())), Some(&2));
+    }
+
+    #[test]
+    fn get_set() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert_eq!(cell.get(), 1);
+        cell.set(2);
+        assert_eq!(cell.get(), 2);
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+    }
+
+    #[test]
+    #[should_fail]
+    fn set_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let _borrow = cell.borrow();
+        cell.set(2);
+    }
+
+    #[test]
+    fn update() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u64);
+        assert_eq!(cell.update(|generation| generation + 1), 2);
+        assert_eq!(cell.get(), 2);
+    }
+
+    #[test]
+    #[should_fail]
+    fn update_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u64);
+        let _borrow = cell.borrow();
+        cell.update(|generation| generation + 1);
+    }
+
+    #[test]
+    fn refcell_round_trip() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::from_refcell(RefCell::new(1u32));
+        *cell.borrow_mut() += 1;
+        let cell = cell.into_refcell();
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    #[test]
+    fn borrow_split_mut() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new((1u32, vec![1u32]));
+        cell.borrow_split_mut(|count, list| {
+            list.push(*count);
+            *count += 1;
+        });
+        assert_eq!(*cell.borrow(), (2, vec![1, 1]));
+    }
+
+    struct Caches {
+        first: Vec<u32>,
+        second: Vec<u32>,
+    }
+
+    #[test]
+    fn borrow_split_mut_macro() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(Caches { first: vec![1], second: vec![] });
+        borrow_split_mut!(cell, first, second => {
+            second.push(first.pop().unwrap());
+        });
+        assert!(cell.borrow().first.is_empty());
+        assert_eq!(cell.borrow().second, vec![1]);
+    }
+
+    #[test]
+    fn try_into_inner() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert_eq!(cell.try_into_inner().ok(), Some(1));
+    }
+
+    #[test]
+    fn try_into_inner_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        mem::forget(cell.borrow());
+        let cell = cell.try_into_inner().err().unwrap();
+        assert_eq!(cell.borrow_state(), BorrowState::Shared);
+        // Dropping a cell with a leaked borrow asserts in debug builds.
+        mem::forget(cell);
+    }
+
+    static BORROW_PANIC_REPORTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+    fn borrow_panic_hook(info: BorrowPanicInfo) {
+        if info.kind == BorrowEventKind::Mutable && info.field_name == Some("Test.field") {
+            BORROW_PANIC_REPORTED.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn borrow_panic_hook_is_called() {
+        let result = Builder::new().scoped(move || {
+            task_state::initialize(SCRIPT);
+            set_borrow_panic_hook(borrow_panic_hook);
+            let cell = DOMRefCell::new(1u32);
+            let _borrow = cell.borrow();
+            cell.borrow_mut_named("Test.field");
+        }).join();
+        assert!(result.is_err());
+        assert!(BORROW_PANIC_REPORTED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn borrow_slice() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![1u32, 2, 3]);
+        for value in cell.borrow_slice_mut().iter_mut() {
+            *value *= 2;
+        }
+        let sum = cell.borrow_slice().iter().fold(0, |sum, value| sum + *value);
+        assert_eq!(sum, 12);
+    }
+
+    struct PoisonCheck<'a> {
+        cell: &'a Poisonable<u32>,
+        sender: Sender<Option<BorrowFailure>>,
+    }
+
+    #[unsafe_destructor]
+    impl<'a> Drop for PoisonCheck<'a> {
+        fn drop(&mut self) {
+            let reason = self.cell.try_borrow_checked().err().map(|error| error.reason);
+            let _ = self.sender.send(reason);
+        }
+    }
+
+    #[test]
+    fn poisonable() {
+        let (sender, receiver) = channel();
+        let result = Builder::new().scoped(move || {
+            task_state::initialize(SCRIPT);
+            let cell = DOMRefCell::new_poisonable(1u32);
+            // Dropped during unwinding, after `_value`.
+            let _check = PoisonCheck { cell: &cell, sender: sender };
+            let _value = cell.borrow_mut();
+            panic!("failed mid-mutation");
+        }).join();
+        assert!(result.is_err());
+        assert_eq!(receiver.recv().unwrap(), Some(BorrowFailure::Poisoned));
+    }
+
+    #[test]
+    fn poisonable_without_panic() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new_poisonable(1u32);
+        *cell.borrow_mut() += 1;
+        assert!(!cell.is_poisoned());
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    #[test]
+    fn default() {
+        use std::default::Default;
+
+        task_state::initialize(SCRIPT);
+        let cell: DOMRefCell<Vec<u32>> = Default::default();
+        assert!(cell.borrow().is_empty());
+    }
+
+    #[test]
+    fn take() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![1u32]);
+        assert_eq!(cell.take(), vec![1]);
+        assert!(cell.borrow().is_empty());
+    }
+
+    #[test]
+    fn take_with() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![1u32]);
+        assert_eq!(cell.take_with(|| Vec::with_capacity(8)), vec![1]);
+        assert!(cell.borrow().is_empty());
+        assert!(cell.borrow().capacity() >= 8);
+    }
+
+    #[test]
+    #[should_fail]
+    fn take_with_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![1u32]);
+        let _borrow = cell.borrow();
+        cell.take_with(|| vec![]);
+    }
+
+    #[test]
+    #[cfg(ndebug)]
+    fn release_size() {
+        assert_eq!(mem::size_of::<DOMRefCell<u8>>(), mem::size_of::<RefCell<u8>>());
+        assert_eq!(mem::size_of::<DOMRefCell<Vec<u8>>>(), mem::size_of::<RefCell<Vec<u8>>>());
+    }
+
+    #[test]
+    #[cfg(all(not(ndebug), target_pointer_width = "64"))]
+    fn debug_size() {
+        // CellDiagnostics, plus the drop flag of the debug-only Drop impl.
+        // Every DOM struct grows by this much per cell in debug builds; if
+        // this changes, update the debug sizes in script/tests.rs too.
+        let overhead = mem::size_of::<DOMRefCell<Vec<u8>>>() - mem::size_of::<RefCell<Vec<u8>>>();
+        assert_eq!(overhead, 32);
+    }
+
+    #[test]
+    fn watched() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new_watched(1u32);
+        let dirty = Rc::new(Cell::new(false));
+        let flag = dirty.clone();
+        cell.on_mutate(box move || flag.set(true));
+        {
+            let _borrow = cell.borrow();
+        }
+        assert!(!dirty.get());
+        *cell.borrow_mut() += 1;
+        assert!(dirty.get());
+
+        dirty.set(false);
+        cell.clear_on_mutate();
+        *cell.borrow_mut() += 1;
+        assert!(!dirty.get());
+    }
+
+    #[test]
+    fn replace_traced() {
+        task_state::initialize(SCRIPT);
+        let traced = Rc::new(Cell::new(0));
+        let cell = DOMRefCell::new(TraceCounter { traced: traced.clone() });
+        cell.replace_traced(TraceCounter { traced: traced.clone() }, None);
+        assert_eq!(traced.get(), 0);
+
+        cell.replace_traced(TraceCounter { traced: traced.clone() }, Some(ptr::null_mut()));
+        assert_eq!(traced.get(), 2);
+    }
+
+    #[test]
+    fn borrow_all_mut() {
+        task_state::initialize(SCRIPT);
+        let (a, b, c) = (DOMRefCell::new(1u32), DOMRefCell::new(2u32), DOMRefCell::new(3u32));
+        let cells = [&c, &a, &b];
+        {
+            let mut guards = super::borrow_all_mut(&cells).unwrap();
+            assert_eq!(*guards[0], 3);
+            *guards[1] += 10;
+        }
+        assert_eq!(*a.borrow(), 11);
+    }
+
+    #[test]
+    fn borrow_all_mut_duplicate() {
+        task_state::initialize(SCRIPT);
+        let (a, b) = (DOMRefCell::new(1u32), DOMRefCell::new(2u32));
+        let cells = [&a, &b, &a];
+        assert!(super::borrow_all_mut(&cells).is_none());
+        assert_eq!(a.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(b.borrow_state(), BorrowState::Unborrowed);
+    }
+
+    #[test]
+    fn borrow_all_mut_busy() {
+        task_state::initialize(SCRIPT);
+        let (a, b, c) = (DOMRefCell::new(1u32), DOMRefCell::new(2u32), DOMRefCell::new(3u32));
+        let cells = [&a, &b, &c];
+        let _borrow = b.borrow();
+        assert!(super::borrow_all_mut(&cells).is_none());
+        assert_eq!(a.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(c.borrow_state(), BorrowState::Unborrowed);
+    }
+
+    #[test]
+    #[cfg(not(ndebug))]
+    fn freeze() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        *cell.borrow_mut() += 1;
+        cell.freeze();
+        assert_eq!(*cell.borrow(), 2);
+        assert!(cell.try_borrow_mut().is_none());
+        assert_eq!(cell.try_borrow_mut_checked().err().unwrap().reason,
+                   BorrowFailure::Frozen);
+    }
+
+    #[test]
+    #[should_fail]
+    #[cfg(not(ndebug))]
+    fn borrow_mut_frozen() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        cell.freeze();
+        cell.borrow_mut();
+    }
+
+    #[test]
+    fn borrow_mut_dirtying() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let dirty = Cell::new(false);
+        *cell.borrow_mut_dirtying(&dirty) += 1;
+        assert!(dirty.get());
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    static DIRTIED_ON_CONFLICT: AtomicBool = ATOMIC_BOOL_INIT;
+
+    /// Records whether `dirty` was set when the test thread unwinds.
+    struct DirtyCheck<'a> {
+        dirty: &'a Cell<bool>,
+    }
+
+    #[unsafe_destructor]
+    impl<'a> Drop for DirtyCheck<'a> {
+        fn drop(&mut self) {
+            DIRTIED_ON_CONFLICT.store(self.dirty.get(), Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn borrow_mut_dirtying_conflict() {
+        DIRTIED_ON_CONFLICT.store(true, Ordering::SeqCst);
+        let result = Builder::new().scoped(move || {
+            task_state::initialize(SCRIPT);
+            let cell = DOMRefCell::new(1u32);
+            let dirty = Cell::new(false);
+            let _check = DirtyCheck { dirty: &dirty };
+            let _borrow = cell.borrow();
+            cell.borrow_mut_dirtying(&dirty);
+        }).join();
+        assert!(result.is_err());
+        assert!(!DIRTIED_ON_CONFLICT.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn borrow_mut_split() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new((vec![1u32], 1u32));
+        {
+            let (mut list, mut count) = cell.borrow_mut_split(|pair| (&mut pair.0, &mut pair.1));
+            list.push(*count);
+            *count += 1;
+            drop(list);
+            assert_eq!(cell.borrow_state(), BorrowState::Mutable);
+        }
+        assert_eq!(*cell.borrow(), (vec![1, 1], 2));
+    }
+
+    #[test]
+    fn gc_safe_trace() {
+        task_state::initialize(SCRIPT);
+        let traced = Rc::new(Cell::new(0));
+        let cell = DOMRefCell::new_gc_safe(TraceCounter { traced: traced.clone() });
+        task_state::enter(IN_GC);
+        {
+            let _borrow = cell.borrow_mut();
+            cell.trace(ptr::null_mut());
+        }
+        assert_eq!(traced.get(), 0);
+        cell.trace(ptr::null_mut());
+        task_state::exit(IN_GC);
+        assert_eq!(traced.get(), 1);
+    }
+
+    #[test]
+    fn borrow_boxed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        {
+            let value = cell.borrow_boxed();
+            assert_eq!(**value, 1);
+            assert_eq!(cell.borrow_state(), BorrowState::Shared);
+        }
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+    }
+
+    #[test]
+    fn compare_and_set() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert!(cell.compare_and_set(1, 2));
+        assert_eq!(cell.get(), 2);
+    }
+
+    #[test]
+    fn compare_and_set_mismatch() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert!(!cell.compare_and_set(3, 2));
+        assert_eq!(cell.get(), 1);
+    }
+
+    #[test]
+    #[should_fail]
+    fn compare_and_set_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let _borrow = cell.borrow();
+        cell.compare_and_set(1, 2);
+    }
+
+    #[test]
+    fn trace_cells() {
+        task_state::initialize(SCRIPT);
+        let traced = Rc::new(Cell::new(0));
+        let cells: Vec<DOMRefCell<TraceCounter>> = range(0, 3u).map(|_| {
+            DOMRefCell::new(TraceCounter { traced: traced.clone() })
+        }).collect();
+        task_state::enter(IN_GC);
+        super::trace_cells(cells.as_slice(), ptr::null_mut());
+        task_state::exit(IN_GC);
+        assert_eq!(traced.get(), 3);
+    }
+
+    #[test]
+    fn as_readonly() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let view = cell.as_readonly();
+        assert_eq!(*view.borrow(), 1);
+        *cell.borrow_mut() += 1;
+        assert_eq!(view.with(|value| *value), 2);
+        let _borrow = cell.borrow_mut();
+        assert!(view.try_borrow().is_none());
+    }
+
+    #[test]
+    fn map_into() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let cell = cell.map_into(|value| value.to_string());
+        assert_eq!(*cell.borrow(), "1".to_owned());
+    }
+
+    #[test]
+    fn borrow_mut_scope_macro() {
+        task_state::initialize(SCRIPT);
+        let list = DOMRefCell::new(vec![1u32]);
+        let count = DOMRefCell::new(0u32);
+        let len = borrow_mut_scope!(list => list {
+            borrow_mut_scope!(count => count {
+                *count += 1;
+                list.push(*count);
+            });
+            list.len()
+        });
+        assert_eq!(len, 2);
+        assert_eq!(list.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(count.borrow_state(), BorrowState::Unborrowed);
+    }
+
+    #[test]
+    #[allow(unsafe_blocks)]
+    fn try_borrow_for_gc_trace() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        task_state::enter(IN_GC);
+        unsafe {
+            assert_eq!(cell.try_borrow_for_gc_trace(), Some(&1));
+            let _borrow = cell.borrow_mut();
+            assert!(cell.try_borrow_for_gc_trace().is_none());
+        }
+        task_state::exit(IN_GC);
+    }
+
+    #[test]
+    fn replace_if() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert_eq!(cell.replace_if(|value| *value == 1, 2), Some(1));
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    #[test]
+    fn replace_if_rejected() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert_eq!(cell.replace_if(|value| *value == 3, 2), None);
+        assert_eq!(*cell.borrow(), 1);
+    }
+
+    #[test]
+    #[allow(unsafe_blocks)]
+    fn borrow_mut_leak() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let value = unsafe { cell.borrow_mut_leak() };
+        assert!(cell.has_leaked_borrow());
+        assert_eq!(cell.borrow_state(), BorrowState::Mutable);
+        unsafe {
+            *value += 1;
+            cell.reclaim_leaked_mut();
+        }
+        assert!(!cell.has_leaked_borrow());
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    #[test]
+    fn drain_into() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![2u32, 3]);
+        let mut dest = vec![1u32];
+        cell.drain_into(&mut dest);
+        assert_eq!(dest, vec![1, 2, 3]);
+        assert!(cell.borrow().is_empty());
+    }
+
+    #[test]
+    #[should_fail]
+    fn drain_into_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![2u32, 3]);
+        let _borrow = cell.borrow();
+        cell.drain_into(&mut vec![]);
+    }
+
+    #[test]
+    fn clear() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(Vec::with_capacity(8));
+        cell.borrow_mut().push(1u32);
+        cell.clear();
+        assert!(cell.borrow().is_empty());
+        assert!(cell.borrow().capacity() >= 8);
+    }
+
+    #[test]
+    fn borrow_mut_or_init() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(None);
+        let calls = Cell::new(0u32);
+        for _ in range(0u, 2) {
+            *cell.borrow_mut_or_init(|| {
+                calls.set(calls.get() + 1);
+                vec![]
+            }) = vec![1u32];
+        }
+        assert_eq!(calls.get(), 1);
+        assert_eq!(*cell.borrow(), Some(vec![1]));
+    }
+
+    #[test]
+    fn borrow_try_map() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new("token".to_owned());
+        {
+            let token = cell.borrow_try_map(|s| -> Result<&str, ()> { Ok(s.as_slice()) });
+            assert_eq!(&*token.unwrap(), "token");
+        }
+        {
+            let result = cell.borrow_try_map(|s| -> Result<&str, &'static str> {
+                if s.is_empty() { Ok(s.as_slice()) } else { Err("not empty") }
+            });
+            assert_eq!(result.err(), Some("not empty"));
+        }
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        cell.borrow_mut().push_str("s");
+    }
+
+    #[test]
+    fn borrow_mut_batch() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![0u32]);
+        let ops = range(1u32, 4).map(|n| move |&mut: v: &mut Vec<u32>| v.push(n * 10));
+        cell.borrow_mut_batch(ops);
+        assert_eq!(*cell.borrow(), vec![0, 10, 20, 30]);
+    }
+
+    #[test]
+    #[cfg(not(ndebug))]
+    fn unfreeze() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        assert!(!cell.is_frozen());
+        cell.freeze();
+        assert!(cell.is_frozen());
+        cell.unfreeze();
+        assert!(!cell.is_frozen());
+        *cell.borrow_mut() += 1;
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    #[test]
+    #[cfg(not(ndebug))]
+    fn borrow_timed() {
+        use std::time::duration::Duration;
+        use time;
+
+        struct Timed(u32);
+        struct Untimed;
+
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(Timed(1));
+        assert_eq!(DOMRefCell::<Timed>::total_borrow_time(), Duration::zero());
+        {
+            let value = cell.borrow_timed();
+            assert_eq!(value.0, 1);
+            let start = time::precise_time_ns();
+            while time::precise_time_ns() == start {}
+        }
+        assert!(DOMRefCell::<Timed>::total_borrow_time() > Duration::zero());
+        assert_eq!(DOMRefCell::<Untimed>::total_borrow_time(), Duration::zero());
+    }
+
+    #[test]
+    fn dom_borrow() {
+        fn append_sum<C: DomBorrow<Vec<u32>>>(cell: &C) {
+            let sum = cell.with(|v| v.iter().fold(0, |a, b| a + *b));
+            cell.with_mut(|v| v.push(sum));
+        }
+
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(vec![1u32, 2]);
+        append_sum(&cell);
+        assert_eq!(*cell.borrow(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn show_mutably_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(true);
+        assert_eq!(format!("{:?}", cell).as_slice(), "DOMRefCell { value: true }");
+        let _borrow = cell.borrow_mut();
+        assert_eq!(format!("{:?}", cell).as_slice(), "DOMRefCell { value: <borrowed> }");
+    }
+
+    #[test]
+    #[cfg(not(ndebug))]
+    fn show_off_script_thread() {
+        let result = Builder::new().scoped(move || {
+            format!("{:?}", DOMRefCell::new(true))
+        }).join();
+        assert_eq!(result.ok().unwrap().as_slice(), "DOMRefCell { value: <off script thread> }");
+    }
+
+    #[cfg(not(ndebug))]
+    fn wait_for_clock() {
+        use time;
+
+        let start = time::precise_time_ns();
+        while time::precise_time_ns() - start < 2000000 {}
+    }
+
+    #[test]
+    #[cfg(not(ndebug))]
+    fn borrow_watchdog() {
+        use super::set_borrow_watchdog;
+        use std::time::duration::Duration;
+
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        set_borrow_watchdog(Duration::seconds(60));
+        {
+            let _borrow = cell.borrow_mut();
+            wait_for_clock();
+            assert!(!cell.check_borrow_watchdog());
+        }
+        set_borrow_watchdog(Duration::milliseconds(1));
+        {
+            let _borrow = cell.borrow_mut();
+            wait_for_clock();
+            assert!(cell.check_borrow_watchdog());
+        }
+        assert!(!cell.check_borrow_watchdog());
+    }
+
+    #[test]
+    #[cfg(not(ndebug))]
+    fn borrow_watchdog_untimed_borrow() {
+        use super::{set_borrow_watchdog, clear_borrow_watchdog};
+        use std::time::duration::Duration;
+
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        set_borrow_watchdog(Duration::milliseconds(1));
+        drop(cell.borrow_mut());
+        clear_borrow_watchdog();
+        let _borrow = cell.borrow_mut();
+        set_borrow_watchdog(Duration::milliseconds(1));
+        wait_for_clock();
+        assert!(!cell.check_borrow_watchdog());
+    }
+
+    #[test]
+    fn borrow_map() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new((vec![1u32], 2u32));
+        {
+            let list = cell.borrow_map(|pair| &pair.0);
+            assert_eq!(*list, vec![1]);
+            assert_eq!(cell.borrow_state(), BorrowState::Shared);
+        }
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        {
+            let mut count = cell.borrow_mut_map(|pair| &mut pair.1);
+            *count += 1;
+            assert_eq!(cell.borrow_state(), BorrowState::Mutable);
+        }
+        assert_eq!(cell.borrow_state(), BorrowState::Unborrowed);
+        assert_eq!(*cell.borrow(), (vec![1], 3));
+    }
+
+    #[test]
+    fn try_with_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let called = Cell::new(false);
+        {
+            let _borrow = cell.borrow_mut();
+            assert_eq!(cell.try_with(|_| called.set(true)), None);
+        }
+        {
+            let _borrow = cell.borrow();
+            assert_eq!(cell.try_with_mut(|_| called.set(true)), None);
+        }
+        assert!(!called.get());
+        assert_eq!(cell.try_with(|value| *value), Some(1));
+    }
+
+    #[test]
+    fn try_replace_with_while_borrowed() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let called = Cell::new(false);
+        {
+            let _borrow = cell.borrow();
+            assert_eq!(cell.try_replace_with(|_| { called.set(true); 2 }), None);
+        }
+        assert!(!called.get());
+        assert_eq!(cell.try_replace_with(|value| *value + 1), Some(1));
+        assert_eq!(*cell.borrow(), 2);
+    }
+
+    #[test]
+    fn borrow_or_else() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        let busy = Cell::new(false);
+        assert_eq!(cell.borrow_or_else(|value| *value, || { busy.set(true); 0 }), 1);
+        assert!(!busy.get());
+
+        let ok = Cell::new(false);
+        let _borrow = cell.borrow_mut();
+        assert_eq!(cell.borrow_or_else(|value| { ok.set(true); *value }, || 0), 0);
+        assert!(!ok.get());
+    }
+
+    #[test]
+    fn try_borrow_mut_or_log() {
+        task_state::initialize(SCRIPT);
+        let cell = DOMRefCell::new(1u32);
+        {
+            let _borrow = cell.borrow();
+            assert!(cell.try_borrow_mut_or_log("test").is_none());
         }
+        *cell.try_borrow_mut_or_log("test").unwrap() += 1;
+        assert_eq!(*cell.borrow(), 2);
     }
 }
@@ -4,7 +4,7 @@
 
 //! DOM bindings for `CharacterData`.
 
-use dom::bindings::cell::DOMRefCell;
+use dom::bindings::cell::{DOMRefCell, Ref};
 use dom::bindings::codegen::Bindings::CharacterDataBinding::CharacterDataMethods;
 use dom::bindings::codegen::InheritTypes::{CharacterDataDerived, NodeCast};
 use dom::bindings::error::{Fallible, ErrorResult};
@@ -17,7 +17,6 @@ use dom::node::{Node, NodeHelpers, NodeTypeId};
 use util::str::DOMString;
 
 use std::borrow::ToOwned;
-use std::cell::Ref;
 
 #[dom_struct]
 pub struct CharacterData {
@@ -8,7 +8,7 @@ use dom::activation::Activatable;
 use dom::attr::{Attr, AttrSettingType, AttrHelpers, AttrHelpersForLayout};
 use dom::attr::AttrValue;
 use dom::namednodemap::NamedNodeMap;
-use dom::bindings::cell::DOMRefCell;
+use dom::bindings::cell::{DOMRefCell, Ref, RefMut};
 use dom::bindings::codegen::Bindings::AttrBinding::AttrMethods;
 use dom::bindings::codegen::Bindings::ElementBinding;
 use dom::bindings::codegen::Bindings::ElementBinding::ElementMethods;
@@ -63,7 +63,6 @@ use html5ever::tree_builder::{NoQuirks, LimitedQuirks, Quirks};
 use cssparser::RGBA;
 use std::ascii::AsciiExt;
 use std::borrow::{IntoCow, ToOwned};
-use std::cell::{Ref, RefMut};
 use std::default::Default;
 use std::mem;
 use std::sync::Arc;
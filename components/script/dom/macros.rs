@@ -228,3 +228,27 @@ macro_rules! global_event_handlers(
         event_handler!(change, GetOnchange, SetOnchange);
     )
 );
+
+// Mutably borrows a `DOMRefCell` holding a struct once, binding each of the
+// named fields to a separate `&mut` for the duration of the body.
+#[macro_export]
+macro_rules! borrow_split_mut(
+    ($cell:expr, $($field:ident),+ => $body:expr) => ({
+        let mut guard = $cell.borrow_mut();
+        let value = &mut *guard;
+        $(let $field = &mut value.$field;)+
+        $body
+    });
+);
+
+// Mutably borrows a `DOMRefCell` for the duration of the block, binding the
+// name to a `&mut` to its contents. The borrow is released at the closing
+// brace, and the guard itself cannot escape the block.
+#[macro_export]
+macro_rules! borrow_mut_scope(
+    ($cell:expr => $value:ident $body:block) => ({
+        let mut guard = $cell.borrow_mut();
+        let $value = &mut *guard;
+        $body
+    });
+);
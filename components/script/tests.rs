@@ -17,11 +17,11 @@ use std::mem::size_of;
 // I'd really prefer the tests themselves to be run at plugin time,
 // however rustc::middle doesn't have access to the full type data
 macro_rules! sizeof_checker (
-    ($testname: ident, $t:ty, $known_size:expr) => (
+    ($testname: ident, $t:ty, $known_size:expr, $known_debug_size:expr) => (
         #[test]
         fn $testname() {
             let new = size_of::<$t>();
-            let old = $known_size;
+            let old = if cfg!(ndebug) { $known_size } else { $known_debug_size };
             if new < old {
                 panic!("Your changes have decreased the stack size of commonly used DOM struct {} from {} to {}. \
                         Good work! Please update the size in script/tests.rs",
@@ -38,11 +38,14 @@ macro_rules! sizeof_checker (
 );
 
 // Update the sizes here
-sizeof_checker!(size_event_target, EventTarget, 48);
-sizeof_checker!(size_node, Node, 216);
-sizeof_checker!(size_element, Element, 328);
-sizeof_checker!(size_htmlelement, HTMLElement, 344);
-sizeof_checker!(size_div, HTMLDivElement, 344);
-sizeof_checker!(size_span, HTMLSpanElement, 344);
-sizeof_checker!(size_text, Text, 248);
-sizeof_checker!(size_characterdata, CharacterData, 248);
+//
+// The second size is for debug builds, where every DOMRefCell also carries
+// diagnostic state and a drop flag: 32 more bytes per cell.
+sizeof_checker!(size_event_target, EventTarget, 48, 80);
+sizeof_checker!(size_node, Node, 216, 280);
+sizeof_checker!(size_element, Element, 328, 456);
+sizeof_checker!(size_htmlelement, HTMLElement, 344, 472);
+sizeof_checker!(size_div, HTMLDivElement, 344, 472);
+sizeof_checker!(size_span, HTMLSpanElement, 344, 472);
+sizeof_checker!(size_text, Text, 248, 344);
+sizeof_checker!(size_characterdata, CharacterData, 248, 344);
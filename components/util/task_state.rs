@@ -8,7 +8,7 @@
 //! In release builds, `get` returns 0.  All of the other functions inline
 //! away to nothing.
 
-pub use self::imp::{initialize, get, enter, exit};
+pub use self::imp::{initialize, get, try_get, enter, exit};
 
 bitflags! {
     #[derive(Show)]
@@ -74,6 +74,12 @@ mod imp {
         state
     }
 
+    /// Like `get`, but returns `None` instead of panicking if the state has
+    /// not been initialized on this task.
+    pub fn try_get() -> Option<TaskState> {
+        STATE.with(|ref k| *k.borrow())
+    }
+
     pub fn enter(x: TaskState) {
         let state = get();
         assert!(!state.intersects(x));
@@ -96,6 +102,7 @@ mod imp {
     use super::TaskState;
     #[inline(always)] pub fn initialize(_: TaskState) { }
     #[inline(always)] pub fn get() -> TaskState { TaskState::empty() }
+    #[inline(always)] pub fn try_get() -> Option<TaskState> { Some(TaskState::empty()) }
     #[inline(always)] pub fn enter(_: TaskState) { }
     #[inline(always)] pub fn exit(_: TaskState) { }
 }